@@ -0,0 +1,27 @@
+//! Compares the reference interpreter with the engine which caches decoded instructions.
+
+use advent_of_code_2019::intcode::{FastMachine, Program};
+use criterion::{criterion_group, criterion_main, Criterion, Fun};
+use std::collections::VecDeque;
+
+/// Runs the BOOST program of day 9, which executes a few hundred thousand instructions.
+fn boost(c: &mut Criterion) {
+    let program: Program = include_str!("../input/2019/day9.txt").parse().unwrap();
+
+    let reference = Fun::new("reference", |b, program: &Program| {
+        b.iter(|| {
+            let mut machine = program.machine(VecDeque::from(vec![2]), Vec::new());
+            machine.run().unwrap()
+        })
+    });
+    let fast = Fun::new("fast", |b, program: &Program| {
+        b.iter(|| {
+            let machine = program.machine(VecDeque::from(vec![2]), Vec::new());
+            FastMachine::new(machine).run().unwrap()
+        })
+    });
+    c.bench_functions("day9 boost", vec![reference, fast], program);
+}
+
+criterion_group!(benches, boost);
+criterion_main!(benches);
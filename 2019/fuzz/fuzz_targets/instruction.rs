@@ -0,0 +1,14 @@
+//! Decodes arbitrary words, which must never panic.
+
+#![no_main]
+use advent_of_code_2019::intcode::Instruction;
+use libfuzzer_sys::fuzz_target;
+use std::convert::TryFrom;
+
+fuzz_target!(|word: isize| {
+    if let Ok(instr) = Instruction::try_from(word) {
+        // The canonical encoding decodes to the same instruction
+        let canonical = Instruction::try_from(instr.encode()).ok();
+        assert_eq!(Some(instr), canonical);
+    }
+});
@@ -0,0 +1,18 @@
+//! Runs arbitrary programs on arbitrary input, which must never panic.
+
+#![no_main]
+use advent_of_code_2019::intcode::Machine;
+use libfuzzer_sys::fuzz_target;
+use std::collections::VecDeque;
+
+/// Arbitrary programs may loop forever, hence runs are cut off.
+const MAX_STEPS: usize = 100_000;
+
+fuzz_target!(|data: (Vec<isize>, Vec<isize>)| {
+    let (program, input) = data;
+    let mut machine = Machine::new(program, VecDeque::from(input), Vec::new());
+    machine.set_memory_limit(1 << 20);
+    machine.set_step_limit(Some(MAX_STEPS));
+
+    let _ = machine.run();
+});
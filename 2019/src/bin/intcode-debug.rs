@@ -0,0 +1,206 @@
+//! Interactive debugger for an intcode program read from the file given as the first argument.
+//!
+//! Type `help` at the prompt for the available commands.
+
+use advent_of_code_2019::intcode::debug::{Debugger, Stop, Watch, MAX_LISTING};
+use advent_of_code_2019::intcode::disasm::Item;
+use advent_of_code_2019::intcode::{Program, State};
+use std::io::{BufRead, Write};
+
+type Result<T, E = Box<dyn std::error::Error>> = std::result::Result<T, E>;
+
+const HELP: &str = "\
+step [n]              execute n instructions (default 1)
+back [n]              undo n instructions (default 1)
+rewind <addr>         undo instructions until the one at addr is next
+writer <addr>         show the last instruction which wrote an address
+continue              run until a breakpoint, watchpoint, input request or halt
+break <addr>          set a breakpoint
+delete <addr>         remove a breakpoint
+watch <addr> [r|w|rw] break when an address is read and/or written (default rw)
+unwatch <addr>        remove a watchpoint
+info                  show registers, breakpoints and watchpoints
+dis [addr] [n]        disassemble n instructions (default at pc, 10 instructions)
+x <addr> [len]        dump memory (default 16 words)
+input <values...>     queue input values
+output                show and clear pending output
+quit                  exit the debugger";
+
+fn main() -> Result<()> {
+    let path = std::env::args()
+        .nth(1)
+        .ok_or("usage: intcode-debug <program>")?;
+    let source = std::fs::read_to_string(path)?;
+    let program: Program = source.parse()?;
+
+    let mut debugger = Debugger::new(program.into());
+    print_location(&debugger);
+
+    let stdin = std::io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("(intcode) ");
+        std::io::stdout().flush()?;
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => break,
+        };
+        let words: Vec<_> = line.split_whitespace().collect();
+        if let Some((&command, args)) = words.split_first() {
+            match execute(&mut debugger, command, args) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => println!("error: {}", e),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Executes a command, returning false if the debugger should exit.
+fn execute(debugger: &mut Debugger, command: &str, args: &[&str]) -> Result<bool> {
+    let arg = |i: usize| -> Result<Option<usize>> {
+        args.get(i)
+            .map(|s| {
+                s.parse()
+                    .map_err(|e| format!("invalid number '{}': {}", s, e).into())
+            })
+            .transpose()
+    };
+    // Machines cannot address words beyond isize::MAX
+    let location = |i: usize| -> Result<Option<usize>> {
+        match arg(i)? {
+            Some(a) if a > isize::MAX as usize => {
+                Err(format!("address {} is out of range", a).into())
+            }
+            a => Ok(a),
+        }
+    };
+    let addr = |i: usize| -> Result<usize> { location(i)?.ok_or_else(|| "missing address".into()) };
+    let count = |i: usize, default: usize| -> Result<usize> {
+        match arg(i)?.unwrap_or(default) {
+            n if n > MAX_LISTING => Err(format!("count {} exceeds {}", n, MAX_LISTING).into()),
+            n => Ok(n),
+        }
+    };
+
+    match command {
+        "s" | "step" => {
+            for _ in 0..arg(0)?.unwrap_or(1) {
+                match debugger.step() {
+                    Stop::Machine(State::Running) | Stop::Machine(State::Output(_)) => {}
+                    stop => {
+                        print_stop(&stop);
+                        break;
+                    }
+                }
+            }
+            print_location(debugger);
+        }
+        "back" => {
+            for _ in 0..arg(0)?.unwrap_or(1) {
+                if !debugger.step_back() {
+                    println!("history exhausted");
+                    break;
+                }
+            }
+            print_location(debugger);
+        }
+        "rewind" => {
+            let undone = debugger.run_back_to(addr(0)?);
+            println!("undid {} instructions", undone);
+            print_location(debugger);
+        }
+        "writer" => match debugger.last_write(addr(0)?) {
+            Some((step, pc)) => println!("written at step {} by the instruction at {}", step, pc),
+            None => println!("not written within the history"),
+        },
+        "c" | "continue" => {
+            print_stop(&debugger.resume());
+            print_location(debugger);
+        }
+        "b" | "break" => {
+            debugger.add_breakpoint(addr(0)?);
+        }
+        "d" | "delete" => {
+            if !debugger.remove_breakpoint(addr(0)?) {
+                println!("no such breakpoint");
+            }
+        }
+        "w" | "watch" => {
+            let watch = match args.get(1).copied().unwrap_or("rw") {
+                "r" => Watch::Read,
+                "w" => Watch::Write,
+                "rw" => Watch::ReadWrite,
+                kind => return Err(format!("invalid watch kind '{}'", kind).into()),
+            };
+            debugger.add_watchpoint(addr(0)?, watch);
+        }
+        "unwatch" => {
+            if !debugger.remove_watchpoint(addr(0)?) {
+                println!("no such watchpoint");
+            }
+        }
+        "i" | "info" => {
+            let machine = debugger.machine();
+            println!("pc: {}", machine.pc());
+            println!("relative base: {}", machine.relative_base());
+            println!("pending input: {:?}", machine.input());
+            println!(
+                "breakpoints: {:?}",
+                debugger.breakpoints().collect::<Vec<_>>()
+            );
+            println!(
+                "watchpoints: {:?}",
+                debugger.watchpoints().collect::<Vec<_>>()
+            );
+        }
+        "dis" => {
+            let start = location(0)?.unwrap_or_else(|| debugger.machine().pc());
+            for (addr, item) in debugger.disassemble(start, count(1, 10)?) {
+                print_item(debugger, addr, &item);
+            }
+        }
+        "x" => print!("{}", debugger.dump(addr(0)?, count(1, 16)?)),
+        "input" => {
+            let values = args
+                .iter()
+                .map(|s| {
+                    s.parse()
+                        .map_err(|e| format!("invalid value '{}': {}", s, e))
+                })
+                .collect::<Result<Vec<isize>, _>>()?;
+            debugger.push_input(values);
+        }
+        "o" | "output" => println!("{:?}", debugger.take_output()),
+        "h" | "help" => println!("{}", HELP),
+        "q" | "quit" => return Ok(false),
+        _ => return Err(format!("unknown command '{}', see 'help'", command).into()),
+    }
+    Ok(true)
+}
+
+fn print_stop(stop: &Stop) {
+    match stop {
+        Stop::Breakpoint(addr) => println!("breakpoint at {}", addr),
+        Stop::Watchpoint { pc, access } => println!("watchpoint: {:?} at pc {}", access, pc),
+        Stop::Machine(State::Faulted(fault)) => println!("machine faulted: {}", fault),
+        Stop::Machine(state) => println!("machine: {:?}", state),
+    }
+}
+
+fn print_location(debugger: &Debugger) {
+    let pc = debugger.machine().pc();
+    if let Some((addr, item)) = debugger.disassemble(pc, 1).first() {
+        print_item(debugger, *addr, item);
+    }
+}
+
+fn print_item(debugger: &Debugger, addr: usize, item: &Item) {
+    let marker = if addr == debugger.machine().pc() {
+        "=>"
+    } else {
+        "  "
+    };
+    println!("{} {:>6}: {}", marker, addr, item);
+}
@@ -0,0 +1,41 @@
+//! Disassembles an intcode program read from the file given as the first argument, or from stdin
+//! if no file is given.
+//!
+//! With `--dot` the control-flow graph of the program is printed in the Graphviz DOT language
+//! instead.
+
+use advent_of_code_2019::intcode::disasm::disassemble;
+use advent_of_code_2019::intcode::flow::Graph;
+use advent_of_code_2019::intcode::Program;
+use std::io::Read;
+
+type Result<T, E = Box<dyn std::error::Error>> = std::result::Result<T, E>;
+
+fn main() -> Result<()> {
+    let mut args: Vec<_> = std::env::args().skip(1).collect();
+    let dot = match args.iter().position(|arg| arg == "--dot") {
+        Some(i) => {
+            args.remove(i);
+            true
+        }
+        None => false,
+    };
+
+    let source = match args.first() {
+        Some(path) => std::fs::read_to_string(path)?,
+        None => {
+            let mut source = String::new();
+            std::io::stdin().read_to_string(&mut source)?;
+            source
+        }
+    };
+
+    let program: Program = source.parse()?;
+
+    if dot {
+        print!("{}", Graph::recover(&program).to_dot());
+    } else {
+        print!("{}", disassemble(&program));
+    }
+    Ok(())
+}
@@ -0,0 +1,244 @@
+//! Runs an intcode program read from the file given as the last argument, with its input read
+//! from stdin and its output written to stdout.
+//!
+//! How the program exited is reported on stderr, together with the number of steps it took.
+//! With `--coverage` a coverage report and an annotated dump of memory are written to a file
+//! when the program exits. With `--record` the I/O of the run is written to a session file,
+//! which `--replay` feeds back to the program instead of stdin, failing at the first step where
+//! the program does something else than recorded.
+
+use advent_of_code_2019::intcode::{ascii, io, Coverage, Error, Machine, Program, Session, State};
+use std::collections::VecDeque;
+use std::io::{BufRead, ErrorKind, Write};
+use std::task::Poll;
+
+type Result<T, E = Box<dyn std::error::Error>> = std::result::Result<T, E>;
+
+const USAGE: &str = "\
+usage: intcode-run [options] <program>
+
+options:
+  --ascii               read lines of text and print output as text, values outside the ASCII
+                        range are printed as numbers on their own line
+  --coverage <file>     write a coverage report and annotated memory dump to a file on exit
+  --input <values>      queue input before reading stdin, a comma separated list of values or a
+                        line of text with --ascii (may be repeated)
+  --record <file>       write the input and output of the run to a session file on exit
+  --replay <file>       feed the input of a session file instead of reading stdin and check that
+                        the output matches the recording
+  --set <addr>=<value>  patch memory before running (may be repeated)";
+
+#[derive(Debug, Default)]
+struct Options {
+    ascii: bool,
+    coverage: Option<String>,
+    inputs: Vec<String>,
+    patches: Vec<(usize, isize)>,
+    record: Option<String>,
+    replay: Option<String>,
+    path: String,
+}
+
+fn parse_args() -> Result<Options> {
+    let mut options = Options::default();
+    let mut path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("missing value for {}", arg))
+        };
+        match arg.as_str() {
+            "--ascii" => options.ascii = true,
+            "--coverage" => options.coverage = Some(value()?),
+            "--input" => options.inputs.push(value()?),
+            "--record" => options.record = Some(value()?),
+            "--replay" => options.replay = Some(value()?),
+            "--set" => {
+                let patch = value()?;
+                let (addr, value) = patch
+                    .split_once('=')
+                    .ok_or_else(|| format!("invalid patch '{}', expected addr=value", patch))?;
+                options
+                    .patches
+                    .push((addr.trim().parse()?, value.trim().parse()?));
+            }
+            "-h" | "--help" => return Err(USAGE.into()),
+            _ if arg.starts_with('-') => {
+                return Err(format!("unknown option {}\n{}", arg, USAGE).into())
+            }
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(USAGE.into()),
+        }
+    }
+    options.path = path.ok_or(USAGE)?;
+    if options.replay.is_some() && !options.inputs.is_empty() {
+        return Err("--input cannot be combined with --replay, which provides all input".into());
+    }
+    Ok(options)
+}
+
+/// Parses a comma or whitespace separated list of values.
+fn parse_values(line: &str) -> Result<Vec<isize>> {
+    line.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            s.parse()
+                .map_err(|e| format!("invalid value '{}': {}", s, e).into())
+        })
+        .collect()
+}
+
+/// Encodes input in the mode of the runner.
+fn encode(ascii: bool, line: &str) -> Result<Vec<isize>> {
+    if ascii {
+        Ok(ascii::encode(line)?)
+    } else {
+        parse_values(line)
+    }
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    let options = parse_args()?;
+    let source = std::fs::read_to_string(&options.path)?;
+    let program = source.parse::<Program>()?;
+
+    let mut input = VecDeque::new();
+    for line in &options.inputs {
+        input.extend(encode(options.ascii, line)?);
+    }
+
+    let output = Stdout {
+        ascii: options.ascii,
+        at_line_start: true,
+        error: None,
+    };
+    let mut machine = program.into_machine(input, output);
+    for &(addr, value) in &options.patches {
+        machine
+            .poke(addr, value)
+            .map_err(|e| format!("cannot set {}: {}", addr, e))?;
+    }
+    if options.coverage.is_some() {
+        machine.start_coverage(Coverage::new());
+    }
+    if options.record.is_some() {
+        machine.start_session(Session::new());
+    }
+    let result = match &options.replay {
+        Some(path) => replay(&mut machine, path),
+        None => execute(&mut machine, options.ascii),
+    };
+    // A closed pipe means whoever reads the output has seen enough, e.g. `head`
+    let result = match machine.output_mut().error.take() {
+        Some(e) if e.kind() == ErrorKind::BrokenPipe => Ok(()),
+        Some(e) => Err(e.into()),
+        None => result,
+    };
+    if let (Some(path), Some(session)) = (&options.record, machine.session()) {
+        std::fs::write(path, session.to_string())?;
+    }
+    if let (Some(path), Some(coverage)) = (&options.coverage, machine.coverage()) {
+        let report = format!(
+            "{}\n{}",
+            coverage.report(machine.memory()),
+            coverage.annotate(machine.memory())
+        );
+        std::fs::write(path, report)?;
+    }
+    result
+}
+
+/// Writes output to stdout, as numbers or as text with `--ascii`.
+struct Stdout {
+    ascii: bool,
+    at_line_start: bool,
+    /// The error which closed the output.
+    error: Option<std::io::Error>,
+}
+
+impl Stdout {
+    fn print(&mut self, value: isize) -> std::io::Result<()> {
+        let stdout = std::io::stdout();
+        let mut stdout = stdout.lock();
+        if !self.ascii {
+            writeln!(stdout, "{}", value)?;
+        } else if (0..128).contains(&value) {
+            self.at_line_start = value == isize::from(b'\n');
+            write!(stdout, "{}", value as u8 as char)?;
+        } else {
+            let separator = if self.at_line_start { "" } else { "\n" };
+            self.at_line_start = true;
+            writeln!(stdout, "{}{}", separator, value)?;
+        }
+        stdout.flush()
+    }
+}
+
+impl io::Output<isize> for Stdout {
+    fn write(&mut self, value: isize) -> Result<Poll<()>, Error> {
+        if self.error.is_none() {
+            self.error = self.print(value).err();
+        }
+        match self.error {
+            Some(_) => Err(Error::OutputClosed),
+            None => Ok(Poll::Ready(())),
+        }
+    }
+}
+
+/// Runs the machine until it halts, feeding it the input recorded in a session file.
+fn replay<O: io::Output<isize>>(
+    machine: &mut Machine<VecDeque<isize>, O>,
+    path: &str,
+) -> Result<()> {
+    let session: Session = std::fs::read_to_string(path)?.parse()?;
+    let value = session.replay(machine)?;
+    eprintln!(
+        "replayed, halted with {} after {} steps",
+        value,
+        machine.steps()
+    );
+    Ok(())
+}
+
+/// Runs the machine until it halts, feeding it lines from stdin whenever it needs input.
+fn execute<O: io::Output<isize>>(
+    machine: &mut Machine<VecDeque<isize>, O>,
+    ascii: bool,
+) -> Result<()> {
+    let stdin = std::io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        match machine.run_until_event() {
+            // Writing to stdout never blocks
+            State::Running | State::Output(_) | State::OutputBlocked => {}
+            State::NeedsInput => match lines.next() {
+                Some(line) => {
+                    let values = encode(ascii, &line?)?;
+                    machine.input_mut().extend(values);
+                }
+                None => {
+                    return Err(format!(
+                        "input closed while waiting for input at {} after {} steps",
+                        machine.pc(),
+                        machine.steps()
+                    )
+                    .into())
+                }
+            },
+            State::Halted(value) => {
+                eprintln!("halted with {} after {} steps", value, machine.steps());
+                break Ok(());
+            }
+            State::Faulted(fault) => return Err(format!("faulted: {}", fault).into()),
+        }
+    }
+}
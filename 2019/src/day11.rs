@@ -1,46 +1,38 @@
-use crate::intcode::Machine;
+use crate::intcode::{program, Machine, Program, State};
 use aoc_runner_derive::*;
 use itertools::Itertools;
 use nalgebra::{Matrix2, Point2, Unit, Vector2};
 use num_enum::{IntoPrimitive, TryFromPrimitive};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::convert::TryInto;
-use std::sync::mpsc::sync_channel;
 
 type Error = Box<dyn std::error::Error>;
 type Result<T, E = Error> = std::result::Result<T, E>;
 
+#[aoc_generator(day11)]
+fn gen(input: &str) -> Result<Program, program::Error> {
+    input.parse()
+}
+
 #[aoc(day11, part1)]
-pub fn part1(input: &str) -> Result<usize> {
-    let memory: Vec<isize> = input.split(',').map(|s| s.parse().unwrap()).collect();
-
-    let (mut machine, input, output) = {
-        let (input_tx, input_rx) = sync_channel::<isize>(10);
-        let (output_tx, output_rx) = sync_channel::<isize>(10);
-        (
-            Machine::new(memory, input_rx, output_tx),
-            input_tx,
-            output_rx,
-        )
-    };
+pub fn part1(program: &Program) -> Result<usize> {
+    let mut machine = program.machine(VecDeque::new(), ());
 
     let mut panel = Panel::new(Color::Black);
     let mut robot = Robot::new();
 
     loop {
         let color = panel.color(&robot.pos);
-        input.send((*color).into()).unwrap();
-
-        // Run robot program until next requested input
-        match machine.run() {
-            Err(crate::intcode::Error::WouldBlock) => {}
-            Err(e) => return Err(e.into()),
-            Ok(_) => break,
-        }
+        machine.input_mut().push_back((*color).into());
 
-        // Convert output
-        let (new_color, turn): (Color, Turn) =
-            (output.recv()?.try_into()?, output.recv()?.try_into()?);
+        // Run robot program until it outputs the new color or halts
+        let new_color: Color = match next_output(&mut machine)? {
+            Some(color) => color.try_into()?,
+            None => break,
+        };
+        let turn: Turn = next_output(&mut machine)?
+            .ok_or("missing turn")?
+            .try_into()?;
 
         // Update panel
         panel.paint(robot.pos, new_color);
@@ -54,36 +46,24 @@ pub fn part1(input: &str) -> Result<usize> {
 }
 
 #[aoc(day11, part2)]
-pub fn part2(input: &str) -> Result<String> {
-    let memory: Vec<isize> = input.split(',').map(|s| s.parse().unwrap()).collect();
-
-    let (mut machine, input, output) = {
-        let (input_tx, input_rx) = sync_channel::<isize>(10);
-        let (output_tx, output_rx) = sync_channel::<isize>(10);
-        (
-            Machine::new(memory, input_rx, output_tx),
-            input_tx,
-            output_rx,
-        )
-    };
+pub fn part2(program: &Program) -> Result<String> {
+    let mut machine = program.machine(VecDeque::new(), ());
 
     let mut panel = Panel::new(Color::White);
     let mut robot = Robot::new();
 
     loop {
         let color = panel.color(&robot.pos);
-        input.send((*color).into()).unwrap();
+        machine.input_mut().push_back((*color).into());
 
-        // Run robot program until next requested input
-        match machine.run() {
-            Err(crate::intcode::Error::WouldBlock) => {}
-            Err(e) => return Err(e.into()),
-            Ok(_) => break,
-        }
-
-        // Convert output
-        let (new_color, turn): (Color, Turn) =
-            (output.recv()?.try_into()?, output.recv()?.try_into()?);
+        // Run robot program until it outputs the new color or halts
+        let new_color: Color = match next_output(&mut machine)? {
+            Some(color) => color.try_into()?,
+            None => break,
+        };
+        let turn: Turn = next_output(&mut machine)?
+            .ok_or("missing turn")?
+            .try_into()?;
 
         // Update panel
         panel.paint(robot.pos, new_color);
@@ -96,6 +76,16 @@ pub fn part2(input: &str) -> Result<String> {
     Ok(panel.as_ascii_art())
 }
 
+/// Runs the machine until it outputs a value, returning `None` if it halts instead.
+fn next_output(machine: &mut Machine<VecDeque<isize>, ()>) -> Result<Option<isize>> {
+    match machine.run_until_event() {
+        State::Output(value) => Ok(Some(value)),
+        State::Halted(_) => Ok(None),
+        State::Faulted(e) => Err(e.into()),
+        state => Err(format!("robot stopped: {:?}", state).into()),
+    }
+}
+
 #[derive(Debug)]
 struct Robot {
     pos: Point2<isize>,
@@ -1,25 +1,23 @@
 #![allow(warnings)]
 
-use crate::intcode::Machine;
+use crate::intcode::{program, Program};
 use aoc_runner_derive::*;
+use std::collections::VecDeque;
 use std::convert::{TryFrom, TryInto};
-use std::sync::mpsc::sync_channel;
 
 type Result<T, E = Box<dyn std::error::Error>> = std::result::Result<T, E>;
 
-#[aoc(day5, part1)]
-pub fn part1(input: &str) -> Result<isize> {
-    let memory: Vec<isize> = input.split(',').map(|s| s.parse().unwrap()).collect();
-
-    let (input_tx, input_rx) = sync_channel::<isize>(4);
-    let _ = input_tx.send(1);
-
-    let (output_tx, output_rx) = sync_channel::<isize>(10);
+#[aoc_generator(day5)]
+fn gen(input: &str) -> Result<Program, program::Error> {
+    input.parse()
+}
 
-    let mut machine = Machine::new(memory, input_rx, output_tx);
+#[aoc(day5, part1)]
+pub fn part1(program: &Program) -> Result<isize> {
+    let mut machine = program.machine(VecDeque::from(vec![1]), Vec::new());
     machine.run()?;
 
-    let results: Vec<_> = output_rx.try_iter().collect();
+    let results = machine.output();
     if results.len() == 0 || results.iter().take(results.len() - 1).any(|&x| x != 0) {
         return Err("diagnostic test failed or no output".into());
     }
@@ -28,16 +26,12 @@ pub fn part1(input: &str) -> Result<isize> {
 }
 
 #[aoc(day5, part2)]
-pub fn part2(input: &str) -> Result<isize> {
-    let memory: Vec<isize> = input.split(',').map(|s| s.parse().unwrap()).collect();
-
-    let (input_tx, input_rx) = sync_channel::<isize>(4);
-    let _ = input_tx.send(5);
-
-    let (output_tx, output_rx) = sync_channel::<isize>(1);
-
-    let mut machine = Machine::new(memory, input_rx, output_tx);
+pub fn part2(program: &Program) -> Result<isize> {
+    let mut machine = program.machine(VecDeque::from(vec![5]), VecDeque::new());
     machine.run()?;
 
-    Ok(output_rx.recv()?)
+    machine
+        .output_mut()
+        .pop_front()
+        .ok_or_else(|| "no output".into())
 }
@@ -1,112 +1,118 @@
-use crate::intcode::Machine;
+use crate::intcode::network::Node;
+use crate::intcode::thread::{self, BlockingInput};
+use crate::intcode::{program, Network, Program};
 use aoc_runner_derive::*;
 use fallible_iterator::{convert, FallibleIterator};
 use itertools::Itertools;
-use std::sync::mpsc::sync_channel;
+use std::collections::VecDeque;
+use std::sync::mpsc::channel;
+use std::time::Duration;
 
 type Error = Box<dyn std::error::Error>;
 type Result<T, E = Error> = std::result::Result<T, E>;
 
 const AMPLIFIER_COUNT: usize = 5;
 
-#[aoc(day7, part1)]
-pub fn part1(input: &str) -> Result<isize> {
-    let memory: Vec<isize> = input.split(',').map(|s| s.parse().unwrap()).collect();
+#[aoc_generator(day7)]
+fn gen(input: &str) -> Result<Program, program::Error> {
+    input.parse()
+}
 
+#[aoc(day7, part1)]
+pub fn part1(program: &Program) -> Result<isize> {
     convert(
         (0..AMPLIFIER_COUNT as isize)
             .permutations(AMPLIFIER_COUNT)
             .map(Ok::<Vec<isize>, Error>),
     )
-    .map(|phases| {
-        let (mut txs, mut rxs): (Vec<_>, Vec<_>) =
-            std::iter::repeat_with(|| sync_channel::<isize>(10))
-                .take(AMPLIFIER_COUNT + 1)
-                .unzip();
-
-        // Set phase settings
-        txs.iter_mut()
-            .take(AMPLIFIER_COUNT)
-            .zip(phases)
-            .for_each(|(tx, phase)| tx.send(phase).unwrap());
-
-        // First tx and last rx is the input and output of the system
-        let (input, output) = (txs.remove(0), rxs.remove(AMPLIFIER_COUNT));
-
-        let mut machines: Vec<_> = rxs
-            .drain(..)
-            .zip(txs.drain(..))
-            .map(|(rx, tx)| Machine::new(memory.clone(), rx, tx))
-            .collect();
-
-        input.send(0).unwrap();
-
-        for machine in &mut machines {
-            match machine.run() {
-                Ok(_) | Err(crate::intcode::Error::WouldBlock) => {}
-                Err(e) => return Err(e.into()),
-            }
-        }
-
-        Ok(output.recv().unwrap())
-    })
+    .map(|phases| thrust(Network::pipeline(amplifiers(program, phases))))
     .max()?
     .ok_or_else(|| "no maximum thrust".into())
 }
 
 #[aoc(day7, part2)]
-pub fn part2(input: &str) -> Result<isize> {
-    let memory: Vec<isize> = input.split(',').map(|s| s.parse().unwrap()).collect();
-
+pub fn part2(program: &Program) -> Result<isize> {
     convert(
         (0..AMPLIFIER_COUNT as isize)
             .map(|p| p + 5)
             .permutations(AMPLIFIER_COUNT)
             .map(Ok::<Vec<isize>, Error>),
     )
-    .map(|phases| {
-        let (mut txs, mut rxs): (Vec<_>, Vec<_>) =
-            std::iter::repeat_with(|| sync_channel::<isize>(10))
-                .take(AMPLIFIER_COUNT + 1)
-                .unzip();
-
-        // Set phase settings
-        txs.iter_mut()
-            .take(AMPLIFIER_COUNT)
-            .zip(phases)
-            .for_each(|(tx, phase)| tx.send(phase).unwrap());
+    // The output of the last amplifier is piped back into the first one (feedback loop)
+    .map(|phases| thrust(Network::ring(amplifiers(program, phases))))
+    .max()?
+    .ok_or_else(|| "no maximum thrust".into())
+}
 
-        // First tx and last rx is the input and output of the system
-        let (input, output) = (txs.remove(0), rxs.remove(AMPLIFIER_COUNT));
+#[aoc(day7, part2, threaded)]
+pub fn part2_threaded(program: &Program) -> Result<isize> {
+    convert(
+        (0..AMPLIFIER_COUNT as isize)
+            .map(|p| p + 5)
+            .permutations(AMPLIFIER_COUNT)
+            .map(Ok::<Vec<isize>, Error>),
+    )
+    .map(|phases| threaded_feedback(program, phases))
+    .max()?
+    .ok_or_else(|| "no maximum thrust".into())
+}
 
-        let mut machines: Vec<_> = rxs
-            .drain(..)
-            .zip(txs.drain(..))
-            .map(|(rx, tx)| Machine::new(memory.clone(), rx, tx))
-            .collect();
+/// Creates an amplifier for each phase setting, which it reads before any signal.
+fn amplifiers(program: &Program, phases: Vec<isize>) -> impl Iterator<Item = Node> + '_ {
+    phases
+        .into_iter()
+        .map(move |phase| program.machine(VecDeque::from(vec![phase]), VecDeque::new()))
+}
 
-        // Send input to start of first amplifier
-        input.send(0).unwrap();
+/// Sends a signal of zero into the first amplifier, returning the last signal of the last one.
+fn thrust(mut network: Network) -> Result<isize> {
+    network.send(0, 0);
+    network.run()?;
 
-        let mut running = true;
-        while running {
-            for machine in &mut machines {
-                match machine.run() {
-                    Ok(_) => running = false,
-                    Err(crate::intcode::Error::WouldBlock) => {}
-                    Err(e) => return Err(e.into()),
-                }
-            }
-            if running {
-                // We are still running so we pipe the output into the input (feedback loop)
-                output.try_iter().try_for_each(|v| input.send(v))?;
-            }
-        }
+    network
+        .last_output(AMPLIFIER_COUNT - 1)
+        .copied()
+        .ok_or_else(|| "amplifier produced no output".into())
+}
 
-        Ok(output.recv().unwrap())
-    })
-    .max()?
-    .ok_or_else(|| "no maximum thrust".into())
+/// Runs the feedback loop with every amplifier on its own thread, returning the last signal of
+/// the last amplifier.
+fn threaded_feedback(program: &Program, phases: Vec<isize>) -> Result<isize> {
+    let (txs, rxs): (Vec<_>, Vec<_>) = phases
+        .iter()
+        .map(|&phase| {
+            let (tx, rx) = channel();
+            tx.send(phase).map(|()| (tx, rx))
+        })
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .unzip();
+    txs[0].send(0)?;
+
+    // Each amplifier sends its output to the next one, the last one to the first one
+    let handles: Vec<_> = rxs
+        .into_iter()
+        .zip(txs.into_iter().cycle().skip(1))
+        .map(|(rx, tx)| {
+            let input = BlockingInput::with_timeout(rx, Duration::from_secs(1));
+            thread::spawn(program.machine(input, tx))
+        })
+        .collect();
+
+    let mut exits = Vec::with_capacity(handles.len());
+    for handle in handles {
+        let exit = handle.join();
+        exit.result?;
+        exits.push(exit.machine);
+    }
+
+    // The first amplifier halted before the last signal reached it
+    exits[0]
+        .input()
+        .receiver()
+        .try_iter()
+        .last()
+        .ok_or_else(|| "no thrust produced".into())
 }
 
 #[cfg(test)]
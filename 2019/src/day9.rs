@@ -1,24 +1,22 @@
-use crate::intcode::Machine;
+use crate::intcode::{program, Program};
 use aoc_runner_derive::*;
-use std::sync::mpsc::sync_channel;
+use std::collections::VecDeque;
 
 type Error = Box<dyn std::error::Error>;
 type Result<T, E = Error> = std::result::Result<T, E>;
 
-#[aoc(day9, part1)]
-pub fn part1(input: &str) -> Result<isize> {
-    let memory: Vec<isize> = input.split(',').map(|s| s.parse().unwrap()).collect();
-
-    let (input_tx, input_rx) = sync_channel::<isize>(4);
-    let (output_tx, output_rx) = sync_channel::<isize>(10);
-
-    let mut machine = Machine::new(memory, input_rx, output_tx);
+#[aoc_generator(day9)]
+fn gen(input: &str) -> Result<Program, program::Error> {
+    input.parse()
+}
 
+#[aoc(day9, part1)]
+pub fn part1(program: &Program) -> Result<isize> {
     // Run in test mode
-    let _ = input_tx.send(1);
+    let mut machine = program.machine(VecDeque::from(vec![1]), Vec::new());
     machine.run()?;
 
-    let results: Vec<_> = output_rx.try_iter().collect();
+    let results = machine.output();
     if results.len() != 1 {
         return Err(format!("failed running opcodes: {:?}", results).into());
     }
@@ -27,19 +25,12 @@ pub fn part1(input: &str) -> Result<isize> {
 }
 
 #[aoc(day9, part2)]
-pub fn part2(input: &str) -> Result<isize> {
-    let memory: Vec<isize> = input.split(',').map(|s| s.parse().unwrap()).collect();
-
-    let (input_tx, input_rx) = sync_channel::<isize>(4);
-    let (output_tx, output_rx) = sync_channel::<isize>(4);
-
-    let mut machine = Machine::new(memory, input_rx, output_tx);
-
+pub fn part2(program: &Program) -> Result<isize> {
     // Run in boost mode
-    let _ = input_tx.send(2);
+    let mut machine = program.machine(VecDeque::from(vec![2]), Vec::new());
     machine.run()?;
 
-    let results: Vec<_> = output_rx.try_iter().collect();
+    let results = machine.output();
     if results.len() != 1 {
         return Err(format!("failed running opcodes: {:?}", results).into());
     }
@@ -1,180 +1,713 @@
 use num_enum::{IntoPrimitive, TryFromPrimitive};
-use snafu::{ResultExt as _, Snafu};
+use serde::{Deserialize, Serialize};
+use snafu::{OptionExt as _, ResultExt as _, Snafu};
+use std::collections::VecDeque;
 use std::convert::{TryFrom, TryInto};
-use std::sync::mpsc::{Receiver, SyncSender};
+use std::sync::Arc;
+use std::time::Instant;
+
+pub mod ascii;
+pub mod asm;
+pub mod asynchronous;
+pub mod coverage;
+pub mod debug;
+pub mod disasm;
+pub mod fast;
+pub mod fault;
+pub mod flow;
+pub mod history;
+pub mod io;
+pub mod memory;
+pub mod network;
+pub mod program;
+pub mod registry;
+pub mod session;
+pub mod symbolic;
+pub mod thread;
+pub mod trace;
+pub mod word;
+
+pub use coverage::Coverage;
+pub use debug::Debugger;
+pub use fast::FastMachine;
+pub use fault::Fault;
+pub use history::History;
+pub use io::{Input, Output};
+pub use memory::Memory;
+pub use network::Network;
+pub use program::Program;
+pub use registry::Registry;
+pub use session::Session;
+pub use trace::Trace;
+pub use word::{OverflowPolicy, Word};
+
+use registry::{Call, Effect};
 
 #[derive(Debug, Snafu)]
 pub enum Error {
-    #[snafu(display("operation would block"))]
-    WouldBlock,
     #[snafu(display("requested input but input was closed"))]
     InputClosed,
     #[snafu(display("tried to output but output was closed"))]
     OutputClosed,
+    #[snafu(display("requested input but none was available"))]
+    InputUnavailable,
+    #[snafu(display("tried to output but output was full"))]
+    OutputFull,
+    #[snafu(display("timed out waiting for input"))]
+    InputTimeout,
+    #[snafu(display("timed out waiting for output to accept a value"))]
+    OutputTimeout,
+    #[snafu(display("instruction '{}' is negative", instr))]
+    NegativeInstruction { instr: isize },
     #[snafu(display("invalid opcode in instruction '{}'", instr))]
-    InvalidOpcode {
-        instr: isize,
-        source: num_enum::TryFromPrimitiveError<Opcode>,
-    },
+    InvalidOpcode { instr: isize },
     #[snafu(display("invalid mode in instruction '{}'", instr))]
     InvalidMode {
         instr: isize,
         source: num_enum::TryFromPrimitiveError<Mode>,
     },
+    #[snafu(display("address {} is negative", addr))]
+    NegativeAddress { addr: isize },
+    #[snafu(display("address computation overflowed"))]
+    AddressOverflow,
+    #[snafu(display("writing address {} exceeds the memory limit of {} words", addr, limit))]
+    MemoryLimit { addr: usize, limit: usize },
+    #[snafu(display("instruction at {} does not fit in an isize", pc))]
+    InstructionOutOfRange { pc: usize },
+    #[snafu(display("arithmetic overflow at {}", pc))]
+    Overflow { pc: usize },
+    #[snafu(display("execution budget exceeded after {} steps at {}", steps, pc))]
+    BudgetExceeded { steps: usize, pc: usize },
 }
 
 type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// The state of a machine after executing instructions, see [`Machine::step`].
 #[derive(Debug)]
-pub struct Machine {
+pub enum State<W = isize> {
+    /// An instruction was executed and the machine can keep running.
+    Running,
+    /// The machine is waiting for a value from its input.
+    NeedsInput,
+    /// The machine is waiting for its output to accept a value.
+    OutputBlocked,
+    /// The machine wrote a value to its output.
+    Output(W),
+    /// The machine halted with the given value at address 0.
+    Halted(W),
+    /// The machine failed to execute an instruction.
+    Faulted(Fault),
+}
+
+/// A memory access made by an instruction, see [`Machine::record_accesses`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access<W = isize> {
+    Read { addr: usize, value: W },
+    Write { addr: usize, old: W, new: W },
+}
+
+impl<W> Access<W> {
+    pub fn addr(&self) -> usize {
+        match *self {
+            Access::Read { addr, .. } | Access::Write { addr, .. } => addr,
+        }
+    }
+}
+
+/// The execution state of a machine without its input and output, see [`Machine::snapshot`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot<W = isize> {
+    pc: usize,
+    relative_base: isize,
+    steps: usize,
+    memory: Memory<W>,
+}
+
+/// An intcode machine.
+///
+/// The machine computes with words of type `W`, see [`Word`].
+///
+/// Cloning or (de)serializing a machine captures its full state, including its input and output.
+/// Recorded accesses, traces, histories, coverage and sessions are left out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Machine<I = VecDeque<isize>, O = VecDeque<isize>, W = isize> {
     pc: usize,
-    relative_base: usize,
-    memory: Vec<isize>,
-    input: Receiver<isize>,
-    output: SyncSender<isize>,
+    relative_base: isize,
+    memory: Memory<W>,
+    input: I,
+    output: O,
+    steps: usize,
+    overflow: OverflowPolicy,
+    #[serde(default)]
+    step_limit: Option<usize>,
+    #[serde(skip)]
+    deadline: Option<Instant>,
+    #[serde(skip)]
+    record_accesses: bool,
+    #[serde(skip)]
+    accesses: Vec<Access<W>>,
+    #[serde(skip)]
+    trace: Option<Trace<W>>,
+    #[serde(skip)]
+    history: Option<History<W>>,
+    #[serde(skip)]
+    coverage: Option<Coverage>,
+    #[serde(skip)]
+    session: Option<Session<W>>,
+    /// The input read by the instruction being executed, kept for the history and session.
+    #[serde(skip)]
+    consumed_input: Option<W>,
+    /// The operations to execute, which are the standard ones if `None`.
+    #[serde(skip)]
+    registry: Option<Arc<Registry<I, O, W>>>,
 }
 
-impl Machine {
-    pub fn new(memory: Vec<isize>, input: Receiver<isize>, output: SyncSender<isize>) -> Self {
+impl<I, O> Machine<I, O> {
+    pub fn new(memory: Vec<isize>, input: I, output: O) -> Self {
+        Machine::from_words(memory, input, output)
+    }
+}
+
+impl<I, O, W: Word> Machine<I, O, W> {
+    /// Creates a machine which computes with words of another type than `isize`.
+    pub fn from_words(memory: Vec<W>, input: I, output: O) -> Self {
         Machine {
             pc: 0,
             relative_base: 0,
-            memory,
+            memory: memory.into(),
             input,
             output,
+            steps: 0,
+            overflow: OverflowPolicy::default(),
+            step_limit: None,
+            deadline: None,
+            record_accesses: false,
+            accesses: Vec::new(),
+            trace: None,
+            history: None,
+            coverage: None,
+            session: None,
+            consumed_input: None,
+            registry: None,
         }
     }
-    fn execute(&mut self, instr: Instruction) -> Result<Status> {
-        // Create arguments
-        let mut args = [
-            self.pc as isize + 1,
-            self.pc as isize + 2,
-            self.pc as isize + 3,
-        ];
-        args[..instr.opcode.arg_count()]
-            .iter_mut()
-            .zip(instr.modes.iter())
-            .for_each(|(arg, mode)| match mode {
-                Mode::Position => *arg = self.addr(*arg as usize),
-                Mode::Relative => *arg = self.addr(*arg as usize) + self.relative_base as isize,
-                Mode::Immidiate => {}
-            });
 
-        match instr.opcode {
-            Opcode::Add => {
-                let [a, b, c] = args;
-                let value = self.addr(a as usize) + self.addr(b as usize);
-                self.set_addr(c as usize, value);
-                Ok(Status::Advance(instr.opcode.arg_count() + 1))
+    /// Creates a machine which continues from a snapshot.
+    ///
+    /// Snapshots share their memory with the machine until either is written, hence taking and
+    /// restoring them is cheap.
+    pub fn from_snapshot(snapshot: Snapshot<W>, input: I, output: O) -> Self {
+        let mut machine = Machine::from_words(Vec::new(), input, output);
+        machine.restore(snapshot);
+        machine
+    }
+
+    /// Captures the execution state of the machine, which unlike cloning works for any input
+    /// and output.
+    pub fn snapshot(&self) -> Snapshot<W> {
+        Snapshot {
+            pc: self.pc,
+            relative_base: self.relative_base,
+            steps: self.steps,
+            memory: self.memory.clone(),
+        }
+    }
+
+    /// Resets the execution state of the machine to a snapshot, keeping its input and output.
+    ///
+    /// The history is cleared, since it cannot undo the jump to the snapshot.
+    pub fn restore(&mut self, snapshot: Snapshot<W>) {
+        self.pc = snapshot.pc;
+        self.relative_base = snapshot.relative_base;
+        self.steps = snapshot.steps;
+        self.memory = snapshot.memory;
+        self.accesses.clear();
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+    }
+
+    /// The address of the next instruction.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn relative_base(&self) -> isize {
+        self.relative_base
+    }
+
+    /// The number of instructions executed so far.
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    pub fn memory(&self) -> &Memory<W> {
+        &self.memory
+    }
+
+    /// Limits how many words of memory the machine may allocate, see [`Memory::set_limit`].
+    pub fn set_memory_limit(&mut self, words: usize) {
+        self.memory.set_limit(words);
+    }
+
+    /// Reads an address, which unlike executing instructions is never logged as an access.
+    pub fn peek(&self, addr: usize) -> W {
+        self.memory.get(addr)
+    }
+
+    /// Writes an address, which unlike executing instructions is never logged as an access.
+    ///
+    /// Fails with `Error::AddressOverflow` for addresses no instruction could refer to, and with
+    /// `Error::MemoryLimit` if the write needs more memory than the limit allows.
+    pub fn poke(&mut self, addr: usize, value: W) -> Result<(), Error> {
+        snafu::ensure!(addr <= isize::MAX as usize, AddressOverflow);
+        self.memory.set(addr, value)
+    }
+
+    /// Sets what happens when an addition or multiplication overflows, which by default is a fault.
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) {
+        self.overflow = policy;
+    }
+
+    /// Limits the total number of steps the machine may execute, after which every step fails
+    /// with `Error::BudgetExceeded` without executing anything.
+    ///
+    /// Raising or removing the limit lets the machine continue where it stopped.
+    pub fn set_step_limit(&mut self, steps: Option<usize>) {
+        self.step_limit = steps;
+    }
+
+    /// Sets a point in time after which every step fails with `Error::BudgetExceeded` without
+    /// executing anything, see [`Machine::set_step_limit`].
+    ///
+    /// The clock is only checked every few hundred steps, hence the deadline may be overrun
+    /// slightly. Deadlines are not serialized.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// Fails if the machine may not execute another step.
+    fn check_budget(&self) -> Result<()> {
+        let over_limit = matches!(self.step_limit, Some(limit) if self.steps >= limit);
+        let past_deadline = matches!(self.deadline, Some(deadline)
+            if self.steps.is_multiple_of(DEADLINE_INTERVAL) && Instant::now() >= deadline);
+        snafu::ensure!(
+            !over_limit && !past_deadline,
+            BudgetExceeded {
+                steps: self.steps,
+                pc: self.pc
             }
-            Opcode::Mul => {
-                let [a, b, c] = args;
-                let value = self.addr(a as usize) * self.addr(b as usize);
-                self.set_addr(c as usize, value);
-                Ok(Status::Advance(instr.opcode.arg_count() + 1))
+        );
+        Ok(())
+    }
+
+    /// Sets the operations the machine executes, which are shared with other machines using the
+    /// same registry.
+    ///
+    /// Registries are not serialized, a deserialized machine executes the standard operations.
+    pub fn set_registry(&mut self, registry: Arc<Registry<I, O, W>>) {
+        self.registry = Some(registry);
+    }
+
+    /// Starts or stops recording the data accesses made by each step.
+    ///
+    /// Reading instructions and their immediate arguments does not count as a data access.
+    pub fn record_accesses(&mut self, enabled: bool) {
+        self.record_accesses = enabled;
+        self.accesses.clear();
+    }
+
+    /// The data accesses made by the last step, which is empty unless recording.
+    pub fn accesses(&self) -> &[Access<W>] {
+        &self.accesses
+    }
+
+    /// Starts recording every executed instruction into `trace`.
+    pub fn start_trace(&mut self, trace: Trace<W>) {
+        self.trace = Some(trace);
+    }
+
+    /// Stops tracing, returning the trace recorded so far.
+    pub fn stop_trace(&mut self) -> Option<Trace<W>> {
+        self.trace.take()
+    }
+
+    pub fn trace(&self) -> Option<&Trace<W>> {
+        self.trace.as_ref()
+    }
+
+    /// Starts recording an undo log into `history`, see [`Machine::step_back`].
+    pub fn start_history(&mut self, history: History<W>) {
+        self.history = Some(history);
+    }
+
+    /// Stops recording the undo log, returning it.
+    pub fn stop_history(&mut self) -> Option<History<W>> {
+        self.history.take()
+    }
+
+    pub fn history(&self) -> Option<&History<W>> {
+        self.history.as_ref()
+    }
+
+    /// Undoes the last executed instruction recorded in the history, returning its entry.
+    ///
+    /// Inputs and outputs cannot be rewound in general, hence the value the instruction read or
+    /// wrote is only part of the returned entry. Returns `None` without changing anything if the
+    /// history is disabled or exhausted.
+    pub fn step_back(&mut self) -> Option<history::Entry<W>> {
+        let entry = self.history.as_mut()?.pop()?;
+        for (addr, old) in entry.writes.iter().rev() {
+            // The address was written before, hence its page is allocated unless it holds zero
+            self.memory
+                .set(*addr, old.clone())
+                .expect("restoring a written address never allocates");
+        }
+        self.pc = entry.pc;
+        self.relative_base = entry.relative_base;
+        self.steps = entry.step;
+        self.accesses.clear();
+        Some(entry)
+    }
+
+    /// Steps back until the instruction at `pc` is the next to execute again, returning the
+    /// undone entries, most recent first.
+    ///
+    /// At least one instruction is undone. If the history runs out first, the machine stays at
+    /// the oldest recorded instruction.
+    pub fn run_back_to(&mut self, pc: usize) -> Vec<history::Entry<W>> {
+        let mut undone = Vec::new();
+        while let Some(entry) = self.step_back() {
+            undone.push(entry);
+            if self.pc == pc {
+                break;
             }
-            Opcode::Input => {
-                use std::sync::mpsc::TryRecvError;
-                let [a, _, _] = args;
-                let value = self.input.try_recv().map_err(|e| match e {
-                    TryRecvError::Empty => Error::WouldBlock,
-                    TryRecvError::Disconnected => Error::InputClosed,
-                })?;
-                self.set_addr(a as usize, value);
-                Ok(Status::Advance(instr.opcode.arg_count() + 1))
+        }
+        undone
+    }
+
+    /// Starts counting how each address is used into `coverage`.
+    ///
+    /// Instructions undone by [`Machine::step_back`] stay counted.
+    pub fn start_coverage(&mut self, coverage: Coverage) {
+        self.coverage = Some(coverage);
+    }
+
+    /// Stops counting, returning the coverage recorded so far.
+    pub fn stop_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /// Starts recording every consumed input and produced output into `session`, see
+    /// [`Session::replay`].
+    pub fn start_session(&mut self, session: Session<W>) {
+        self.session = Some(session);
+    }
+
+    /// Stops recording I/O, returning the session recorded so far.
+    pub fn stop_session(&mut self) -> Option<Session<W>> {
+        self.session.take()
+    }
+
+    pub fn session(&self) -> Option<&Session<W>> {
+        self.session.as_ref()
+    }
+
+    /// Whether the data accesses of each step have to be logged.
+    fn logging(&self) -> bool {
+        self.record_accesses
+            || self.trace.is_some()
+            || self.history.is_some()
+            || self.coverage.is_some()
+    }
+
+    pub fn input(&self) -> &I {
+        &self.input
+    }
+
+    pub fn input_mut(&mut self) -> &mut I {
+        &mut self.input
+    }
+
+    pub fn output(&self) -> &O {
+        &self.output
+    }
+
+    pub fn output_mut(&mut self) -> &mut O {
+        &mut self.output
+    }
+
+    /// Consumes the machine, returning its input and output.
+    pub fn into_io(self) -> (I, O) {
+        (self.input, self.output)
+    }
+}
+
+impl<I: Input<W>, O: Output<W>, W: Word> Machine<I, O, W> {
+    /// The address argument `i` of the instruction at pc refers to.
+    fn arg_addr(&self, modes: &[Mode; 3], i: usize) -> Result<usize> {
+        let addr = self.pc + 1 + i;
+        match modes[i] {
+            Mode::Position => to_addr(&self.peek(addr)),
+            Mode::Relative => self
+                .peek(addr)
+                .to_isize()
+                .and_then(|offset| offset.checked_add(self.relative_base))
+                .context(AddressOverflow)
+                .and_then(|addr| to_addr(&addr)),
+            Mode::Immidiate => Ok(addr),
+        }
+    }
+
+    /// Loads the value of argument `i` of the instruction at pc.
+    fn load(&mut self, modes: &[Mode; 3], i: usize) -> Result<W> {
+        match modes[i] {
+            // Immediate values are part of the instruction, hence they are not a data access
+            Mode::Immidiate => Ok(self.peek(self.pc + 1 + i)),
+            _ => {
+                let addr = self.arg_addr(modes, i)?;
+                let value = self.peek(addr);
+                if self.logging() {
+                    self.accesses.push(Access::Read {
+                        addr,
+                        value: value.clone(),
+                    });
+                }
+                Ok(value)
             }
-            Opcode::Output => {
-                use std::sync::mpsc::TrySendError;
-                let [a, _, _] = args;
-                let value = self.addr(a as usize);
-                // Ignore failures when reciving channel is dropped (sending to void is okay)
-                self.output.try_send(value).map_err(|e| match e {
-                    TrySendError::Full(_) => Error::WouldBlock,
-                    TrySendError::Disconnected(_) => Error::OutputClosed,
-                })?;
-                Ok(Status::Advance(instr.opcode.arg_count() + 1))
+        }
+    }
+
+    /// Fails like [`Machine::store`] would for argument `i`, without storing anything.
+    fn check_store(&self, modes: &[Mode; 3], i: usize) -> Result<()> {
+        let addr = self.arg_addr(modes, i)?;
+        self.memory.check(addr)
+    }
+
+    /// Stores a value where argument `i` of the instruction at pc refers to.
+    fn store(&mut self, modes: &[Mode; 3], i: usize, value: W) -> Result<()> {
+        let addr = self.arg_addr(modes, i)?;
+        if self.logging() {
+            let old = self.peek(addr);
+            self.memory.set(addr, value.clone())?;
+            self.accesses.push(Access::Write {
+                addr,
+                old,
+                new: value,
+            });
+            Ok(())
+        } else {
+            self.memory.set(addr, value)
+        }
+    }
+
+    /// Executes a single instruction.
+    ///
+    /// An instruction which has to wait for input or output is not executed, hence it is retried
+    /// on the next step.
+    pub fn step(&mut self) -> State<W> {
+        match self.try_step() {
+            Ok(state) => state,
+            // The instruction did not execute, hence its partial accesses did not happen either
+            Err(e) => {
+                self.accesses.clear();
+                State::Faulted(self.fault(e))
             }
-            Opcode::JumpNotZero => {
-                let [a, b, _] = args;
-                if self.addr(a as usize) != 0 {
-                    Ok(Status::Jump(self.addr(b as usize) as usize))
-                } else {
-                    Ok(Status::Advance(instr.opcode.arg_count() + 1))
-                }
+        }
+    }
+
+    /// Captures the context of an error raised by the instruction at pc.
+    fn fault(&self, error: Error) -> Fault {
+        Fault::capture(error, self.pc, self.relative_base, self.steps, &self.memory)
+    }
+
+    /// Executes a single instruction, leaving the machine unchanged on errors as long as the
+    /// operation checks its arguments before consuming input, like the standard ones do.
+    fn try_step(&mut self) -> Result<State<W>> {
+        self.accesses.clear();
+        self.consumed_input = None;
+        let (pc, relative_base) = (self.pc, self.relative_base);
+        // Addresses of operands and the next instruction are computed from pc unchecked
+        snafu::ensure!(
+            pc.checked_add(MAX_INSTRUCTION_LEN).is_some(),
+            AddressOverflow
+        );
+        self.check_budget()?;
+        let instr = self
+            .peek(self.pc)
+            .to_isize()
+            .context(InstructionOutOfRange { pc })?;
+        snafu::ensure!(instr >= 0, NegativeInstruction { instr });
+        let opcode = (instr % 100) as u8;
+        let operation = match &self.registry {
+            Some(registry) => registry.get(opcode).copied(),
+            None => Opcode::try_from(opcode).ok().map(registry::builtin),
+        }
+        .context(InvalidOpcode { instr })?;
+        let modes = decode_modes(instr)?;
+        let operands = match self.trace {
+            Some(_) => self.resolve_operands(operation.arg_count, &modes)?,
+            None => Vec::new(),
+        };
+
+        let next = operation.arg_count + 1;
+        let mut jumped = false;
+        let state = match (operation.handler)(&mut Call::new(self, modes))? {
+            Effect::Continue => {
+                self.pc += next;
+                State::Running
             }
-            Opcode::JumpZero => {
-                let [a, b, _] = args;
-                if self.addr(a as usize) == 0 {
-                    Ok(Status::Jump(self.addr(b as usize) as usize))
-                } else {
-                    Ok(Status::Advance(instr.opcode.arg_count() + 1))
-                }
+            Effect::Jump(new_pc) => {
+                self.pc = new_pc;
+                jumped = true;
+                State::Running
             }
-            Opcode::LessThan => {
-                let [a, b, c] = args;
-                if self.addr(a as usize) < self.addr(b as usize) {
-                    self.set_addr(c as usize, 1);
-                } else {
-                    self.set_addr(c as usize, 0);
-                }
-                Ok(Status::Advance(instr.opcode.arg_count() + 1))
+            Effect::Output(value) => {
+                self.pc += next;
+                State::Output(value)
             }
-            Opcode::Equal => {
-                let [a, b, c] = args;
-                if self.addr(a as usize) == self.addr(b as usize) {
-                    self.set_addr(c as usize, 1);
-                } else {
-                    self.set_addr(c as usize, 0);
-                }
-                Ok(Status::Advance(instr.opcode.arg_count() + 1))
+            // Nothing was executed, hence nothing was accessed either
+            Effect::NeedsInput => {
+                self.accesses.clear();
+                return Ok(State::NeedsInput);
+            }
+            Effect::OutputBlocked => {
+                self.accesses.clear();
+                return Ok(State::OutputBlocked);
             }
-            Opcode::OffsetRBase => {
-                let [a, _, _] = args;
-                self.relative_base += self.addr(a as usize) as usize;
-                Ok(Status::Advance(instr.opcode.arg_count() + 1))
+            Effect::Halt => State::Halted(self.peek(0)),
+        };
+
+        if let Some(trace) = &mut self.trace {
+            trace.push(trace::Record {
+                step: self.steps,
+                pc,
+                relative_base,
+                opcode,
+                mnemonic: operation.mnemonic,
+                modes,
+                operands,
+                writes: self
+                    .accesses
+                    .iter()
+                    .filter_map(|access| match access {
+                        Access::Write { addr, new, .. } => Some((*addr, new.clone())),
+                        Access::Read { .. } => None,
+                    })
+                    .collect(),
+            });
+        }
+        let input = self.consumed_input.take();
+        if let Some(session) = &mut self.session {
+            if let Some(value) = &input {
+                session.push(session::Event::Input {
+                    step: self.steps,
+                    value: value.clone(),
+                });
             }
-            Opcode::Halt => Ok(Status::Halt),
         }
+        if let Some(history) = &mut self.history {
+            history.push(history::Entry {
+                step: self.steps,
+                pc,
+                relative_base,
+                writes: self
+                    .accesses
+                    .iter()
+                    .filter_map(|access| match access {
+                        Access::Write { addr, old, .. } => Some((*addr, old.clone())),
+                        Access::Read { .. } => None,
+                    })
+                    .collect(),
+                input,
+                output: match &state {
+                    State::Output(value) => Some(value.clone()),
+                    _ => None,
+                },
+            });
+        }
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(pc, operation.arg_count, jumped, &self.accesses);
+        }
+        if let (Some(session), State::Output(value)) = (&mut self.session, &state) {
+            session.push(session::Event::Output {
+                step: self.steps,
+                value: value.clone(),
+            });
+        }
+        self.steps += 1;
+        Ok(state)
     }
 
-    fn addr(&mut self, addr: usize) -> isize {
-        if self.memory.len() <= addr {
-            self.memory.resize(addr + 1, 0);
-        }
-        self.memory[addr]
+    /// Resolves the arguments of the instruction at pc without executing it.
+    fn resolve_operands(
+        &self,
+        arg_count: usize,
+        modes: &[Mode; 3],
+    ) -> Result<Vec<trace::Operand<W>>> {
+        (0..arg_count)
+            .map(|i| match modes[i] {
+                Mode::Immidiate => Ok(trace::Operand {
+                    addr: None,
+                    value: self.peek(self.pc + 1 + i),
+                }),
+                _ => {
+                    let addr = self.arg_addr(modes, i)?;
+                    Ok(trace::Operand {
+                        addr: Some(addr),
+                        value: self.peek(addr),
+                    })
+                }
+            })
+            .collect()
     }
 
-    fn set_addr(&mut self, addr: usize, value: isize) {
-        if self.memory.len() <= addr {
-            self.memory.resize(addr + 1, 0);
+    /// Executes instructions until the machine outputs a value, has to wait, halts or faults.
+    ///
+    /// The returned state is never `State::Running`.
+    pub fn run_until_event(&mut self) -> State<W> {
+        loop {
+            match self.step() {
+                State::Running => {}
+                state => break state,
+            }
         }
-        self.memory[addr] = value;
     }
 
-    pub fn run(&mut self) -> Result<isize> {
+    /// Runs the machine until it halts, returning the value at address 0.
+    ///
+    /// Nothing can resolve a wait while running to completion, hence waiting for input or output
+    /// is reported as an error.
+    pub fn run(&mut self) -> Result<W, Fault> {
         loop {
-            let instr = Instruction::try_from(self.addr(self.pc))?;
-            match self.execute(instr)? {
-                Status::Advance(incr) => {
-                    self.pc += incr;
-                }
-                Status::Jump(new_pc) => {
-                    self.pc = new_pc;
-                }
-                Status::Halt => break Ok(self.addr(0)),
+            match self.run_until_event() {
+                State::Running | State::Output(_) => {}
+                State::NeedsInput => break Err(self.fault(Error::InputUnavailable)),
+                State::OutputBlocked => break Err(self.fault(Error::OutputFull)),
+                State::Halted(value) => break Ok(value),
+                State::Faulted(e) => break Err(e),
             }
         }
     }
 }
 
-#[derive(Debug)]
-enum Status {
-    Advance(usize),
-    Jump(usize),
-    Halt,
+/// Converts a computed address, which may be negative or not even fit in an `isize`.
+fn to_addr<W: Word>(word: &W) -> Result<usize> {
+    let addr = word.to_isize().context(AddressOverflow)?;
+    usize::try_from(addr).ok().context(NegativeAddress { addr })
 }
 
-#[derive(Debug, PartialEq, TryFromPrimitive, IntoPrimitive)]
+/// The number of words of the longest instruction.
+const MAX_INSTRUCTION_LEN: usize = 4;
+
+/// How many steps are executed between checks of the deadline, see [`Machine::set_deadline`].
+const DEADLINE_INTERVAL: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, TryFromPrimitive, IntoPrimitive)]
 #[repr(u8)]
 pub enum Opcode {
     Add = 1,
@@ -204,9 +737,25 @@ impl Opcode {
             Opcode::Halt => 0,
         }
     }
+
+    /// The name of the opcode in assembly listings.
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Opcode::Add => "ADD",
+            Opcode::Mul => "MUL",
+            Opcode::Input => "IN",
+            Opcode::Output => "OUT",
+            Opcode::JumpNotZero => "JNZ",
+            Opcode::JumpZero => "JZ",
+            Opcode::LessThan => "LT",
+            Opcode::Equal => "EQ",
+            Opcode::OffsetRBase => "ARB",
+            Opcode::Halt => "HALT",
+        }
+    }
 }
 
-#[derive(Debug, PartialEq, TryFromPrimitive, IntoPrimitive)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, TryFromPrimitive, IntoPrimitive)]
 #[repr(u8)]
 pub enum Mode {
     Position = 0,
@@ -214,31 +763,372 @@ pub enum Mode {
     Relative = 2,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Instruction {
     opcode: Opcode,
     modes: [Mode; 3],
 }
 
+impl Instruction {
+    pub fn opcode(&self) -> Opcode {
+        self.opcode
+    }
+
+    /// The modes of the arguments the opcode takes.
+    pub fn modes(&self) -> &[Mode] {
+        &self.modes[..self.opcode.arg_count()]
+    }
+
+    /// Encodes the instruction, leaving the modes of unused arguments as `Mode::Position`.
+    ///
+    /// Several words decode to the same instruction (e.g. `1099` and `99`), this returns the
+    /// canonical one.
+    pub fn encode(&self) -> isize {
+        self.modes[..self.opcode.arg_count()]
+            .iter()
+            .zip(&[100, 1000, 10000])
+            .map(|(&mode, scale)| u8::from(mode) as isize * scale)
+            .sum::<isize>()
+            + u8::from(self.opcode) as isize
+    }
+}
+
 impl TryFrom<isize> for Instruction {
     type Error = Error;
 
     fn try_from(instr: isize) -> Result<Self, Self::Error> {
+        snafu::ensure!(instr >= 0, NegativeInstruction { instr });
         Ok(Instruction {
-            opcode: ((instr % 100) as u8)
-                .try_into()
+            opcode: Opcode::try_from((instr % 100) as u8)
+                .ok()
                 .context(InvalidOpcode { instr })?,
-            modes: [
-                (((instr / 100) % 10) as u8)
-                    .try_into()
-                    .context(InvalidMode { instr })?,
-                (((instr / 1000) % 10) as u8)
-                    .try_into()
-                    .context(InvalidMode { instr })?,
-                (((instr / 10000) % 10) as u8)
-                    .try_into()
-                    .context(InvalidMode { instr })?,
-            ],
+            modes: decode_modes(instr)?,
         })
     }
 }
+
+/// Decodes the modes of a non-negative instruction, whatever its opcode.
+fn decode_modes(instr: isize) -> Result<[Mode; 3]> {
+    Ok([
+        (((instr / 100) % 10) as u8)
+            .try_into()
+            .context(InvalidMode { instr })?,
+        (((instr / 1000) % 10) as u8)
+            .try_into()
+            .context(InvalidMode { instr })?,
+        (((instr / 10000) % 10) as u8)
+            .try_into()
+            .context(InvalidMode { instr })?,
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_until_halt() {
+        let mut machine = Machine::new(vec![1002, 4, 3, 4, 33], VecDeque::new(), ());
+
+        assert_eq!(1002, machine.run().unwrap());
+        assert_eq!(99, machine.peek(4));
+    }
+
+    #[test]
+    fn step_yields_on_input_and_output() {
+        // Echoes a single value
+        let mut machine = Machine::new(vec![3, 0, 4, 0, 99], VecDeque::new(), Vec::new());
+
+        assert!(matches!(machine.step(), State::NeedsInput));
+        machine.input_mut().push_back(7);
+        assert!(matches!(machine.step(), State::Running));
+        assert!(matches!(machine.step(), State::Output(7)));
+        assert!(matches!(machine.step(), State::Halted(7)));
+        assert_eq!(&[7], &machine.output()[..]);
+    }
+
+    #[test]
+    fn run_fails_when_starved() {
+        let mut machine = Machine::new(vec![3, 0, 99], VecDeque::new(), ());
+
+        assert!(matches!(
+            machine.run(),
+            Err(Fault {
+                error: Error::InputUnavailable,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn clones_fork_execution() {
+        // Adds the two inputs
+        let mut machine = Machine::new(
+            vec![3, 9, 3, 10, 1, 9, 10, 0, 99, 0, 0],
+            VecDeque::new(),
+            (),
+        );
+        machine.input_mut().push_back(1);
+        assert!(matches!(machine.run_until_event(), State::NeedsInput));
+
+        let mut fork = machine.clone();
+        machine.input_mut().push_back(2);
+        fork.input_mut().push_back(3);
+        assert_eq!(3, machine.run().unwrap());
+        assert_eq!(4, fork.run().unwrap());
+    }
+
+    #[test]
+    fn snapshots_restore_state() {
+        let mut machine = Machine::new(
+            vec![3, 9, 3, 10, 1, 9, 10, 0, 99, 0, 0],
+            VecDeque::new(),
+            (),
+        );
+        machine.input_mut().push_back(1);
+        assert!(matches!(machine.run_until_event(), State::NeedsInput));
+
+        let json = serde_json::to_string(&machine.snapshot()).unwrap();
+        machine.input_mut().push_back(2);
+        assert_eq!(3, machine.run().unwrap());
+
+        machine.restore(serde_json::from_str(&json).unwrap());
+        machine.input_mut().push_back(5);
+        assert_eq!(6, machine.run().unwrap());
+    }
+
+    #[test]
+    fn serializes_buffered_io() {
+        let mut machine = Machine::new(vec![3, 0, 4, 0, 99], VecDeque::from(vec![7]), Vec::new());
+        assert!(matches!(machine.run_until_event(), State::Output(7)));
+
+        let json = serde_json::to_string(&machine).unwrap();
+        let mut resumed: Machine<VecDeque<isize>, Vec<isize>> =
+            serde_json::from_str(&json).unwrap();
+        assert_eq!(&[7], &resumed.output()[..]);
+        assert_eq!(7, resumed.run().unwrap());
+    }
+
+    #[test]
+    fn relative_base_may_be_negative() {
+        // Moves the relative base below zero and writes relative to it
+        let mut machine = Machine::new(vec![109, -5, 21101, 2, 3, 14, 99], VecDeque::new(), ());
+
+        assert_eq!(109, machine.run().unwrap());
+        assert_eq!(-5, machine.relative_base());
+        assert_eq!(5, machine.peek(9));
+    }
+
+    #[test]
+    fn negative_address_faults() {
+        let mut machine = Machine::new(vec![1, -1, 0, 0, 99], VecDeque::new(), ());
+
+        assert!(matches!(
+            machine.run(),
+            Err(Fault {
+                error: Error::NegativeAddress { addr: -1 },
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn writes_far_away_within_limit() {
+        let mut machine = Machine::new(
+            vec![1101, 1, 2, 1 << 40, 1101, 1, 2, 1 << 50, 99],
+            VecDeque::new(),
+            (),
+        );
+        machine.set_memory_limit(2 * memory::PAGE_SIZE);
+
+        assert!(matches!(
+            machine.run(),
+            Err(Fault { error: Error::MemoryLimit { addr, .. }, .. }) if addr == 1 << 50
+        ));
+        assert_eq!(3, machine.peek(1 << 40));
+        assert_eq!(4, machine.pc());
+    }
+
+    #[test]
+    fn keeps_input_when_store_fails() {
+        let mut machine = Machine::new(vec![203, -5, 99], VecDeque::from(vec![7, 8]), ());
+        assert!(matches!(
+            machine.step(),
+            State::Faulted(Fault {
+                error: Error::NegativeAddress { addr: -5 },
+                ..
+            })
+        ));
+        assert_eq!(&VecDeque::from(vec![7, 8]), machine.input());
+
+        let mut machine = Machine::new(vec![3, 1 << 40, 99], VecDeque::from(vec![7]), ());
+        machine.set_memory_limit(memory::PAGE_SIZE);
+        assert!(matches!(
+            machine.step(),
+            State::Faulted(Fault {
+                error: Error::MemoryLimit { .. },
+                ..
+            })
+        ));
+        assert_eq!(&VecDeque::from(vec![7]), machine.input());
+    }
+
+    #[test]
+    fn pokes_within_limit() {
+        let mut machine = Machine::new(vec![99], (), ());
+        machine.set_memory_limit(2 * memory::PAGE_SIZE);
+
+        machine.poke(1 << 40, 5).unwrap();
+        assert_eq!(5, machine.peek(1 << 40));
+        assert!(matches!(
+            machine.poke(1 << 50, 6),
+            Err(Error::MemoryLimit { addr, .. }) if addr == 1 << 50
+        ));
+        assert!(matches!(
+            machine.poke(usize::MAX, 7),
+            Err(Error::AddressOverflow)
+        ));
+    }
+
+    #[test]
+    fn overflow_policies() {
+        // Doubles the largest word
+        let program = vec![1102, isize::MAX, 2, 0, 99];
+
+        let mut machine = Machine::new(program.clone(), VecDeque::new(), ());
+        assert!(matches!(
+            machine.run(),
+            Err(Fault {
+                error: Error::Overflow { pc: 0 },
+                ..
+            })
+        ));
+
+        let mut machine = Machine::new(program.clone(), VecDeque::new(), ());
+        machine.set_overflow_policy(OverflowPolicy::Wrap);
+        assert_eq!(-2, machine.run().unwrap());
+
+        let mut machine = Machine::new(program, VecDeque::new(), ());
+        machine.set_overflow_policy(OverflowPolicy::Saturate);
+        assert_eq!(isize::MAX, machine.run().unwrap());
+    }
+
+    #[test]
+    fn wide_words() {
+        let program = vec![1102, isize::MAX as i128, 2, 0, 4, 0, 99];
+        let mut machine = Machine::from_words(program, VecDeque::new(), Vec::new());
+
+        assert_eq!(2 * isize::MAX as i128, machine.run().unwrap());
+        assert_eq!(&[2 * isize::MAX as i128], &machine.output()[..]);
+    }
+
+    #[test]
+    fn invalid_opcode_faults() {
+        let mut machine = Machine::new(vec![42], VecDeque::new(), ());
+
+        assert!(matches!(
+            machine.run_until_event(),
+            State::Faulted(Fault {
+                error: Error::InvalidOpcode { instr: 42, .. },
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn budget_is_resumable() {
+        // Counts 9 down to zero
+        let mut machine =
+            Machine::new(vec![1001, 8, -1, 8, 1005, 8, 0, 99, 9], VecDeque::new(), ());
+        machine.set_step_limit(Some(10));
+
+        assert!(matches!(
+            machine.run(),
+            Err(Fault {
+                error: Error::BudgetExceeded { steps: 10, pc: 0 },
+                ..
+            })
+        ));
+        assert_eq!(4, machine.peek(8));
+        machine.set_step_limit(None);
+        assert_eq!(1001, machine.run().unwrap());
+        assert_eq!(19, machine.steps());
+    }
+
+    #[test]
+    fn deadline_stops_endless_loop() {
+        let mut machine = Machine::new(vec![1105, 1, 0], VecDeque::new(), ());
+        machine.set_deadline(Some(Instant::now() + std::time::Duration::from_millis(10)));
+
+        assert!(matches!(
+            machine.run(),
+            Err(Fault {
+                error: Error::BudgetExceeded { pc: 0, .. },
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn malformed_state_faults() {
+        let mut machine = Machine::new(vec![-1], VecDeque::new(), ());
+        assert!(matches!(
+            machine.step(),
+            State::Faulted(Fault {
+                error: Error::NegativeInstruction { instr: -1 },
+                ..
+            })
+        ));
+
+        let mut state = serde_json::to_value(&machine).unwrap();
+        state["pc"] = usize::MAX.into();
+        let mut machine: Machine<VecDeque<isize>, ()> = serde_json::from_value(state).unwrap();
+        assert!(matches!(
+            machine.step(),
+            State::Faulted(Fault {
+                error: Error::AddressOverflow,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn arbitrary_programs_never_panic() {
+        // Random words, biased towards instructions and edge cases
+        let mut words = vec![
+            0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 99, -1, 109, 203, 204, 1105, 2106, 21101, 22201, 11199,
+        ];
+        words.extend(&[1 << 40, isize::MAX, isize::MIN]);
+        let mut seed: u64 = 0x9e37_79b9_7f4a_7c15;
+        let mut random = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed as usize
+        };
+
+        for i in 0..10_000 {
+            let mut word = || match random() % 4 {
+                0 => random() as isize,
+                _ => words[random() % words.len()],
+            };
+            let len = 1 + word().rem_euclid(16) as usize;
+            let program: Vec<_> = (0..len).map(|_| word()).collect();
+            let input: VecDeque<_> = (0..4).map(|_| word()).collect();
+
+            let mut machine = Machine::new(program, input, Vec::new());
+            machine.set_overflow_policy(match i % 3 {
+                0 => OverflowPolicy::Error,
+                1 => OverflowPolicy::Wrap,
+                _ => OverflowPolicy::Saturate,
+            });
+            machine.record_accesses(i % 2 == 0);
+            for _ in 0..100 {
+                match machine.step() {
+                    State::Running | State::Output(_) => {}
+                    _ => break,
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! Talking to intcode programs in ASCII.
+//!
+//! Many programs print prompts and read commands as ASCII text, one character per value. A
+//! [`TextOutput`] assembles what such a program outputs into lines, and a [`Terminal`] wraps a
+//! machine with a line-oriented request/response interface on top of that. Values outside the
+//! ASCII range, such as a final answer, are kept apart as [`Item::Value`] instead of being
+//! garbled into characters.
+
+use super::{Machine, Output, State};
+use snafu::{ensure, Snafu};
+use std::collections::VecDeque;
+use std::mem;
+use std::task::Poll;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("line {:?} is not ASCII", line))]
+    NonAscii { line: String },
+    #[snafu(display("machine faulted: {}", source))]
+    Fault { source: super::Fault },
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Something a program output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Item {
+    /// A line of text, without its newline.
+    Line(String),
+    /// A value which is not an ASCII character.
+    Value(isize),
+}
+
+/// Encodes a line as the values of its characters, followed by a newline.
+pub fn encode(line: &str) -> Result<Vec<isize>> {
+    ensure!(line.is_ascii(), NonAscii { line });
+    Ok(line.bytes().chain(Some(b'\n')).map(isize::from).collect())
+}
+
+/// An output which collects text into lines, see [`Item`].
+#[derive(Debug, Clone, Default)]
+pub struct TextOutput {
+    partial: String,
+    items: VecDeque<Item>,
+}
+
+impl TextOutput {
+    pub fn new() -> Self {
+        TextOutput::default()
+    }
+
+    /// Removes the oldest item which is complete.
+    pub fn pop(&mut self) -> Option<Item> {
+        self.items.pop_front()
+    }
+
+    /// Removes every item which is complete.
+    pub fn take(&mut self) -> Vec<Item> {
+        self.items.drain(..).collect()
+    }
+
+    /// Text not terminated by a newline yet, such as a prompt.
+    pub fn partial(&self) -> &str {
+        &self.partial
+    }
+}
+
+impl Output for TextOutput {
+    fn write(&mut self, value: isize) -> super::Result<Poll<()>> {
+        if (0..128).contains(&value) {
+            match value as u8 as char {
+                '\n' => self
+                    .items
+                    .push_back(Item::Line(mem::take(&mut self.partial))),
+                c => self.partial.push(c),
+            }
+        } else {
+            // Text before the value is its own line, to keep everything in order
+            if !self.partial.is_empty() {
+                self.items
+                    .push_back(Item::Line(mem::take(&mut self.partial)));
+            }
+            self.items.push_back(Item::Value(value));
+        }
+        Ok(Poll::Ready(()))
+    }
+}
+
+/// Why [`Terminal::run`] returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// The program waits for another line.
+    NeedsInput,
+    /// The program halted with the given value at address 0.
+    Halted(isize),
+}
+
+/// A machine which is sent lines of text and answers with items.
+#[derive(Debug, Clone)]
+pub struct Terminal {
+    machine: Machine<VecDeque<isize>, TextOutput>,
+}
+
+impl Terminal {
+    pub fn new(program: Vec<isize>) -> Self {
+        Terminal::from_machine(Machine::new(program, VecDeque::new(), TextOutput::new()))
+    }
+
+    pub fn from_machine(machine: Machine<VecDeque<isize>, TextOutput>) -> Self {
+        Terminal { machine }
+    }
+
+    pub fn machine(&self) -> &Machine<VecDeque<isize>, TextOutput> {
+        &self.machine
+    }
+
+    pub fn machine_mut(&mut self) -> &mut Machine<VecDeque<isize>, TextOutput> {
+        &mut self.machine
+    }
+
+    pub fn into_machine(self) -> Machine<VecDeque<isize>, TextOutput> {
+        self.machine
+    }
+
+    /// Queues a line of input, a newline is appended.
+    pub fn send(&mut self, line: &str) -> Result<()> {
+        self.machine.input_mut().extend(encode(line)?);
+        Ok(())
+    }
+
+    /// Runs until the program waits for more input than was sent, or halts.
+    pub fn run(&mut self) -> Result<Status> {
+        loop {
+            match self.machine.run_until_event() {
+                // Text output never blocks
+                State::Running | State::Output(_) | State::OutputBlocked => {}
+                State::NeedsInput => break Ok(Status::NeedsInput),
+                State::Halted(value) => break Ok(Status::Halted(value)),
+                State::Faulted(source) => break Err(Error::Fault { source }),
+            }
+        }
+    }
+
+    /// Removes every complete item output so far.
+    pub fn output(&mut self) -> Vec<Item> {
+        self.machine.output_mut().take()
+    }
+
+    /// Sends a line and runs until the program waits for the next one or halts, returning what
+    /// it output in the meantime.
+    pub fn request(&mut self, line: &str) -> Result<(Status, Vec<Item>)> {
+        self.send(line)?;
+        let status = self.run()?;
+        Ok((status, self.output()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Asks for a line, echoes it and halts after outputting 1000
+    fn echo() -> Terminal {
+        Terminal::new(vec![
+            104, 72, 104, 105, 104, 63, 104, 10, 3, 100, 4, 100, 1008, 100, 10, 101, 1006, 101, 8,
+            104, 1000, 99,
+        ])
+    }
+
+    #[test]
+    fn separates_values_from_text() {
+        let mut output = TextOutput::new();
+        for value in encode("ab").unwrap().into_iter().chain(vec![99, 1000, 99]) {
+            assert_eq!(Poll::Ready(()), output.write(value).unwrap());
+        }
+
+        assert_eq!(
+            vec![
+                Item::Line("ab".to_string()),
+                Item::Line("c".to_string()),
+                Item::Value(1000),
+            ],
+            output.take()
+        );
+        assert_eq!("c", output.partial());
+    }
+
+    #[test]
+    fn request_response() {
+        let mut terminal = echo();
+        assert_eq!(Status::NeedsInput, terminal.run().unwrap());
+        assert_eq!(vec![Item::Line("Hi?".to_string())], terminal.output());
+
+        let (status, items) = terminal.request("hello").unwrap();
+        assert_eq!(Status::Halted(104), status);
+        assert_eq!(
+            vec![Item::Line("hello".to_string()), Item::Value(1000)],
+            items
+        );
+    }
+
+    #[test]
+    fn rejects_non_ascii() {
+        let mut terminal = echo();
+
+        assert!(matches!(
+            terminal.send("héllo"),
+            Err(Error::NonAscii { .. })
+        ));
+        assert!(terminal.machine().input().is_empty());
+    }
+}
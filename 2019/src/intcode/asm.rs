@@ -0,0 +1,334 @@
+//! Assembler for intcode programs.
+//!
+//! Every line holds an optional label, followed by an instruction or a `DATA` directive, followed
+//! by an optional `;` comment:
+//!
+//! ```text
+//!         IN   [count]          ; read how many values to echo
+//! loop:   IN   [value]
+//!         OUT  [value]
+//!         ADD  [count], #-1, [count]
+//!         JNZ  [count], #loop
+//!         HALT
+//! count:  DATA 0
+//! value:  DATA 0
+//! ```
+//!
+//! Mnemonics are the ones of [`Opcode::mnemonic`] and are case insensitive. Operands are written
+//! as `[x]` (position), `#x` (immediate) or `rb+x`/`rb-x` (relative), where `x` is a sum of
+//! integers and labels such as `count+1`. A label may be used before it is defined. A numeric
+//! label (e.g. `12:`) asserts the address of the line instead of defining a label, which is what
+//! makes the output of the [disassembler](super::disasm) assemble back into the same program.
+
+use super::{Instruction, Mode, Opcode};
+use snafu::{ensure, OptionExt as _, Snafu};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("line {}: unknown mnemonic '{}'", line, mnemonic))]
+    UnknownMnemonic { line: usize, mnemonic: String },
+    #[snafu(display(
+        "line {}: '{}' takes {} operands but got {}",
+        line,
+        mnemonic,
+        expected,
+        found
+    ))]
+    OperandCount {
+        line: usize,
+        mnemonic: String,
+        expected: usize,
+        found: usize,
+    },
+    #[snafu(display("line {}: invalid operand '{}'", line, operand))]
+    InvalidOperand { line: usize, operand: String },
+    #[snafu(display("line {}: invalid label '{}'", line, label))]
+    InvalidLabel { line: usize, label: String },
+    #[snafu(display("line {}: label '{}' is already defined", line, label))]
+    DuplicateLabel { line: usize, label: String },
+    #[snafu(display("line {}: undefined label '{}'", line, label))]
+    UndefinedLabel { line: usize, label: String },
+    #[snafu(display("line {}: expected address {} but is at {}", line, expected, actual))]
+    AddressMismatch {
+        line: usize,
+        expected: usize,
+        actual: usize,
+    },
+    #[snafu(display("line {}: value of '{}' overflows", line, expr))]
+    Overflow { line: usize, expr: String },
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+const OPCODES: [Opcode; 10] = [
+    Opcode::Add,
+    Opcode::Mul,
+    Opcode::Input,
+    Opcode::Output,
+    Opcode::JumpNotZero,
+    Opcode::JumpZero,
+    Opcode::LessThan,
+    Opcode::Equal,
+    Opcode::OffsetRBase,
+    Opcode::Halt,
+];
+
+#[derive(Debug)]
+enum Statement<'a> {
+    Instruction(Opcode, Vec<(Mode, &'a str)>),
+    Data(Vec<&'a str>),
+}
+
+impl Statement<'_> {
+    fn size(&self) -> usize {
+        match self {
+            Statement::Instruction(_, operands) => 1 + operands.len(),
+            Statement::Data(values) => values.len(),
+        }
+    }
+}
+
+/// Assembles a program, returning its memory.
+pub fn assemble(source: &str) -> Result<Vec<isize>> {
+    // First pass assigns addresses to labels and statements
+    let mut labels = HashMap::new();
+    let mut statements = Vec::new();
+    let mut addr = 0;
+    for (i, line) in source.lines().enumerate() {
+        let line_nr = i + 1;
+        let mut rest = line.split(';').next().unwrap_or("").trim();
+
+        while let Some((label, tail)) = split_label(rest) {
+            if let Ok(expected) = label.parse::<usize>() {
+                ensure!(
+                    expected == addr,
+                    AddressMismatch {
+                        line: line_nr,
+                        expected,
+                        actual: addr
+                    }
+                );
+            } else {
+                ensure!(
+                    is_identifier(label),
+                    InvalidLabel {
+                        line: line_nr,
+                        label
+                    }
+                );
+                ensure!(
+                    labels.insert(label, addr).is_none(),
+                    DuplicateLabel {
+                        line: line_nr,
+                        label
+                    }
+                );
+            }
+            rest = tail;
+        }
+
+        if rest.is_empty() {
+            continue;
+        }
+        let statement = parse_statement(line_nr, rest)?;
+        addr += statement.size();
+        statements.push((line_nr, statement));
+    }
+
+    // Second pass resolves operands
+    let mut memory = Vec::with_capacity(addr);
+    for (line_nr, statement) in statements {
+        match statement {
+            Statement::Instruction(opcode, operands) => {
+                let mut modes = [Mode::Position; 3];
+                modes
+                    .iter_mut()
+                    .zip(&operands)
+                    .for_each(|(mode, &(operand_mode, _))| *mode = operand_mode);
+                memory.push(Instruction { opcode, modes }.encode());
+                for (_, expr) in operands {
+                    memory.push(eval(line_nr, expr, &labels)?);
+                }
+            }
+            Statement::Data(values) => {
+                for expr in values {
+                    memory.push(eval(line_nr, expr, &labels)?);
+                }
+            }
+        }
+    }
+    Ok(memory)
+}
+
+/// Splits a leading `label:` off a line.
+fn split_label(line: &str) -> Option<(&str, &str)> {
+    let colon = line.find(':')?;
+    let label = line[..colon].trim();
+    if label.is_empty() || label.contains(char::is_whitespace) {
+        return None;
+    }
+    Some((label, line[colon + 1..].trim_start()))
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_statement(line: usize, s: &str) -> Result<Statement<'_>> {
+    let (mnemonic, rest) = match s.find(char::is_whitespace) {
+        Some(i) => (&s[..i], s[i..].trim()),
+        None => (s, ""),
+    };
+    let args: Vec<_> = if rest.is_empty() {
+        Vec::new()
+    } else {
+        rest.split(',').map(str::trim).collect()
+    };
+
+    if mnemonic.eq_ignore_ascii_case("DATA") {
+        return Ok(Statement::Data(args));
+    }
+
+    let opcode = OPCODES
+        .iter()
+        .copied()
+        .find(|opcode| opcode.mnemonic().eq_ignore_ascii_case(mnemonic))
+        .context(UnknownMnemonic { line, mnemonic })?;
+    ensure!(
+        args.len() == opcode.arg_count(),
+        OperandCount {
+            line,
+            mnemonic,
+            expected: opcode.arg_count(),
+            found: args.len()
+        }
+    );
+
+    let operands = args
+        .into_iter()
+        .map(|operand| parse_operand(operand).context(InvalidOperand { line, operand }))
+        .collect::<Result<_>>()?;
+    Ok(Statement::Instruction(opcode, operands))
+}
+
+fn parse_operand(s: &str) -> Option<(Mode, &str)> {
+    if let Some(addr) = s.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+        Some((Mode::Position, addr))
+    } else if let Some(value) = s.strip_prefix('#') {
+        Some((Mode::Immidiate, value))
+    } else if s == "rb" {
+        Some((Mode::Relative, "0"))
+    } else if s.starts_with("rb+") || s.starts_with("rb-") {
+        Some((Mode::Relative, &s[2..]))
+    } else {
+        None
+    }
+}
+
+/// Evaluates a sum of integers and labels such as `-loop+3`.
+fn eval(line: usize, expr: &str, labels: &HashMap<&str, usize>) -> Result<isize> {
+    // Sum in a wider type so that e.g. `DATA -9223372036854775808` can be assembled
+    let mut sum: i128 = 0;
+    let mut rest = expr.trim();
+    loop {
+        let (negative, tail) = match rest.strip_prefix('-') {
+            Some(tail) => (true, tail),
+            None => (false, rest.strip_prefix('+').unwrap_or(rest)),
+        };
+        let end = tail.find(['+', '-']).unwrap_or(tail.len());
+        let term = tail[..end].trim();
+
+        let value = match term.parse::<i128>() {
+            Ok(value) => value,
+            Err(_) if is_identifier(term) => *labels
+                .get(term)
+                .context(UndefinedLabel { line, label: term })?
+                as i128,
+            Err(_) => {
+                return InvalidOperand {
+                    line,
+                    operand: expr,
+                }
+                .fail()
+            }
+        };
+        sum = if negative {
+            sum.checked_sub(value)
+        } else {
+            sum.checked_add(value)
+        }
+        .context(Overflow { line, expr })?;
+
+        rest = tail[end..].trim_start();
+        if rest.is_empty() {
+            break TryFrom::try_from(sum).ok().context(Overflow { line, expr });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::disasm::disassemble;
+    use crate::intcode::Machine;
+    use std::collections::VecDeque;
+
+    #[test]
+    fn operand_modes() {
+        let program = assemble("MUL [4], #3, [4]\nDATA 33").unwrap();
+
+        assert_eq!(vec![1002, 4, 3, 4, 33], program);
+    }
+
+    #[test]
+    fn forward_references() {
+        let source = concat!(
+            "        IN   [count]\n",
+            "loop:   IN   [value]   ; echo a value\n",
+            "        OUT  [value]\n",
+            "        ADD  [count], #-1, [count]\n",
+            "        jnz  [count], #loop\n",
+            "        halt\n",
+            "count:  data 0\n",
+            "value:  data 0\n",
+        );
+        let program = assemble(source).unwrap();
+
+        let mut machine = Machine::new(program, VecDeque::from(vec![2, 4, 5]), Vec::new());
+        machine.run().unwrap();
+        assert_eq!(&[4, 5], &machine.output()[..]);
+    }
+
+    #[test]
+    fn reports_line_of_error() {
+        let error = assemble("HALT\nADD [1], #2\n").unwrap_err();
+
+        assert_eq!(
+            "line 2: 'ADD' takes 3 operands but got 2",
+            error.to_string()
+        );
+        assert!(matches!(
+            assemble("JZ #0, #nowhere"),
+            Err(Error::UndefinedLabel { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn round_trips_disassembly() {
+        let input = include_str!("../../input/2019/day9.txt");
+        let program: Vec<isize> = input
+            .trim()
+            .split(',')
+            .map(|s| s.parse().unwrap())
+            .collect();
+
+        let listing = disassemble(&program).to_string();
+        assert_eq!(program, assemble(&listing).unwrap());
+    }
+}
@@ -0,0 +1,277 @@
+//! Machines embedded in async code.
+//!
+//! An [`AsyncMachine`] reads its input from a [`Stream`] and writes its output to a [`Sink`].
+//! Running it is a future which suspends whenever the stream has no value ready or the sink has
+//! no room, and which is woken by them once it can continue. [`block_on`] and [`block_on_all`]
+//! form a minimal executor for driving such futures without an external runtime.
+
+use super::{Error, Fault, Input, Machine, Output, Result, State, Word};
+use futures_core::Stream;
+use futures_sink::Sink;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+/// Wakes nobody, used until a machine is polled for the first time.
+struct NoopWaker;
+
+impl Wake for NoopWaker {
+    fn wake(self: Arc<Self>) {}
+}
+
+/// An input which polls a stream on behalf of the task running the machine.
+#[derive(Debug)]
+pub struct StreamInput<S> {
+    stream: S,
+    waker: Waker,
+}
+
+impl<S, W> Input<W> for StreamInput<S>
+where
+    S: Stream<Item = W> + Unpin,
+{
+    fn read(&mut self) -> Result<Poll<W>> {
+        let mut cx = Context::from_waker(&self.waker);
+        match Pin::new(&mut self.stream).poll_next(&mut cx) {
+            Poll::Ready(Some(value)) => Ok(Poll::Ready(value)),
+            Poll::Ready(None) => Err(Error::InputClosed),
+            Poll::Pending => Ok(Poll::Pending),
+        }
+    }
+}
+
+/// An output which feeds a sink on behalf of the task running the machine.
+#[derive(Debug)]
+pub struct SinkOutput<K> {
+    sink: K,
+    waker: Waker,
+}
+
+impl<K> SinkOutput<K> {
+    fn poll_flush<W>(&mut self) -> Poll<Result<()>>
+    where
+        K: Sink<W> + Unpin,
+    {
+        let mut cx = Context::from_waker(&self.waker);
+        Sink::<W>::poll_flush(Pin::new(&mut self.sink), &mut cx).map_err(|_| Error::OutputClosed)
+    }
+}
+
+impl<K, W> Output<W> for SinkOutput<K>
+where
+    K: Sink<W> + Unpin,
+{
+    fn write(&mut self, value: W) -> Result<Poll<()>> {
+        let mut cx = Context::from_waker(&self.waker);
+        match Pin::new(&mut self.sink).poll_ready(&mut cx) {
+            Poll::Ready(Ok(())) => {}
+            Poll::Ready(Err(_)) => return Err(Error::OutputClosed),
+            Poll::Pending => return Ok(Poll::Pending),
+        }
+        Pin::new(&mut self.sink)
+            .start_send(value)
+            .map_err(|_| Error::OutputClosed)?;
+        Ok(Poll::Ready(()))
+    }
+}
+
+/// A machine which reads from a stream and writes to a sink.
+#[derive(Debug)]
+pub struct AsyncMachine<S, K, W = isize> {
+    machine: Machine<StreamInput<S>, SinkOutput<K>, W>,
+}
+
+impl<S, K> AsyncMachine<S, K> {
+    pub fn new(memory: Vec<isize>, input: S, output: K) -> Self {
+        AsyncMachine::from_words(memory, input, output)
+    }
+}
+
+impl<S, K, W: Word> AsyncMachine<S, K, W> {
+    /// Creates a machine which computes with words of another type than `isize`.
+    pub fn from_words(memory: Vec<W>, input: S, output: K) -> Self {
+        let waker = Waker::from(Arc::new(NoopWaker));
+        let input = StreamInput {
+            stream: input,
+            waker: waker.clone(),
+        };
+        let output = SinkOutput {
+            sink: output,
+            waker,
+        };
+        AsyncMachine {
+            machine: Machine::from_words(memory, input, output),
+        }
+    }
+
+    pub fn machine(&self) -> &Machine<StreamInput<S>, SinkOutput<K>, W> {
+        &self.machine
+    }
+
+    pub fn machine_mut(&mut self) -> &mut Machine<StreamInput<S>, SinkOutput<K>, W> {
+        &mut self.machine
+    }
+
+    /// Consumes the machine, returning its stream and sink.
+    pub fn into_io(self) -> (S, K) {
+        let (input, output) = self.machine.into_io();
+        (input.stream, output.sink)
+    }
+}
+
+impl<S, K, W> AsyncMachine<S, K, W>
+where
+    S: Stream<Item = W> + Unpin,
+    K: Sink<W> + Unpin,
+    W: Word,
+{
+    /// Runs the machine until it halts and everything it wrote is flushed, resolving to the value
+    /// at address 0.
+    ///
+    /// The sink is also flushed whenever the machine waits for input, as the value it waits for
+    /// might depend on what it wrote.
+    pub fn run(&mut self) -> Run<'_, S, K, W> {
+        Run {
+            machine: &mut self.machine,
+            halted: None,
+        }
+    }
+}
+
+/// The future returned by [`AsyncMachine::run`].
+#[derive(Debug)]
+pub struct Run<'a, S, K, W> {
+    machine: &'a mut Machine<StreamInput<S>, SinkOutput<K>, W>,
+    halted: Option<W>,
+}
+
+impl<S, K, W> Future for Run<'_, S, K, W>
+where
+    S: Stream<Item = W> + Unpin,
+    K: Sink<W> + Unpin,
+    W: Word + Unpin,
+{
+    type Output = Result<W, Fault>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let run = self.get_mut();
+        let machine = &mut *run.machine;
+        machine.input_mut().waker = cx.waker().clone();
+        machine.output_mut().waker = cx.waker().clone();
+
+        while run.halted.is_none() {
+            match machine.run_until_event() {
+                State::Running | State::Output(_) => {}
+                State::NeedsInput => {
+                    if let Poll::Ready(Err(e)) = machine.output_mut().poll_flush() {
+                        return Poll::Ready(Err(machine.fault(e)));
+                    }
+                    return Poll::Pending;
+                }
+                State::OutputBlocked => return Poll::Pending,
+                State::Halted(value) => run.halted = Some(value),
+                State::Faulted(e) => return Poll::Ready(Err(e)),
+            }
+        }
+
+        match machine.output_mut().poll_flush() {
+            Poll::Ready(Ok(())) => Poll::Ready(Ok(run.halted.take().unwrap())),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(machine.fault(e))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Wakes a thread parked in [`block_on`] or [`block_on_all`].
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Drives a future to completion on the current thread, which is parked while the future waits.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    block_on_all(vec![future]).pop().unwrap()
+}
+
+/// Drives futures concurrently on the current thread until all of them completed, returning
+/// their outputs in order.
+///
+/// Every pending future is polled whenever any of them is woken, which is plenty for a handful of
+/// machines talking to each other.
+pub fn block_on_all<F: Future>(futures: Vec<F>) -> Vec<F::Output> {
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+
+    let mut futures: Vec<_> = futures.into_iter().map(Box::pin).collect();
+    let mut outputs: Vec<_> = futures.iter().map(|_| None).collect();
+    loop {
+        for (future, output) in futures.iter_mut().zip(&mut outputs) {
+            if output.is_none() {
+                if let Poll::Ready(value) = future.as_mut().poll(&mut cx) {
+                    *output = Some(value);
+                }
+            }
+        }
+        if outputs.iter().all(Option::is_some) {
+            break outputs.into_iter().map(Option::unwrap).collect();
+        }
+        thread::park();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_channel::mpsc;
+
+    #[test]
+    fn runs_on_channels() {
+        // Doubles the input
+        let (tx, rx) = mpsc::unbounded();
+        let (out_tx, mut out_rx) = mpsc::unbounded();
+        let mut machine = AsyncMachine::new(vec![3, 9, 1002, 9, 2, 9, 4, 9, 99, 0], rx, out_tx);
+
+        tx.unbounded_send(21).unwrap();
+        assert_eq!(3, block_on(machine.run()).unwrap());
+        assert_eq!(Some(42), out_rx.try_recv().ok());
+    }
+
+    #[test]
+    fn suspends_until_input_arrives() {
+        let (tx, rx) = mpsc::unbounded();
+        let mut machine = AsyncMachine::new(vec![3, 0, 99], rx, mpsc::unbounded().0);
+
+        let mut run = machine.run();
+        let waker = Waker::from(Arc::new(NoopWaker));
+        assert!(Pin::new(&mut run)
+            .poll(&mut Context::from_waker(&waker))
+            .is_pending());
+
+        tx.unbounded_send(7).unwrap();
+        assert_eq!(7, block_on(run).unwrap());
+    }
+
+    #[test]
+    fn machines_talk_through_bounded_channels() {
+        // Outputs 1 to 3, which the other machine adds up
+        let counter = vec![104, 1, 104, 2, 104, 3, 99];
+        let adder = vec![3, 20, 3, 21, 3, 22, 1, 20, 21, 23, 1, 23, 22, 23, 4, 23, 99];
+        let (tx, rx) = mpsc::channel(0);
+        let (out_tx, mut out_rx) = mpsc::unbounded();
+
+        let mut counter = AsyncMachine::new(counter, mpsc::unbounded().1, tx);
+        let mut adder = AsyncMachine::new(adder, rx, out_tx);
+        type Boxed<'a> = Pin<Box<dyn Future<Output = Result<isize, Fault>> + 'a>>;
+        let runs: Vec<Boxed> = vec![Box::pin(counter.run()), Box::pin(adder.run())];
+        let results = block_on_all(runs);
+
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(Some(6), out_rx.try_recv().ok());
+        assert!(counter.machine().steps() > 3);
+    }
+}
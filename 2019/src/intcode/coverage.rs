@@ -0,0 +1,380 @@
+//! Memory access maps, see [`Machine::start_coverage`](super::Machine::start_coverage).
+//!
+//! A [`Coverage`] counts for every address how often it was executed as an instruction, fetched
+//! as an argument, read as data and written. Together with the disassembler this separates code
+//! from data in programs nobody documented, and shows which branches an input exercised.
+
+use super::disasm::Item;
+use super::{Access, Memory, Opcode, MAX_INSTRUCTION_LEN};
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+
+/// How an address was used while recording.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Hits {
+    /// The number of times the instruction at the address was executed.
+    pub executed: usize,
+    /// The number of times executing the instruction at the address jumped elsewhere instead of
+    /// continuing with the next instruction.
+    pub jumped: usize,
+    /// The number of times the word was fetched as an argument of an executed instruction.
+    pub argument: usize,
+    pub read: usize,
+    pub written: usize,
+}
+
+impl Hits {
+    /// Whether the word is part of an executed instruction.
+    pub fn is_code(&self) -> bool {
+        self.executed > 0 || self.argument > 0
+    }
+
+    /// Whether the word was accessed as data.
+    pub fn is_data(&self) -> bool {
+        self.read > 0 || self.written > 0
+    }
+
+    /// The uses of the word as `xarw`, with a dash for each kind of use which did not happen.
+    fn flags(&self) -> String {
+        [
+            (self.executed, 'x'),
+            (self.argument, 'a'),
+            (self.read, 'r'),
+            (self.written, 'w'),
+        ]
+        .iter()
+        .map(|&(n, flag)| if n > 0 { flag } else { '-' })
+        .collect()
+    }
+
+    fn merge(&mut self, other: &Hits) {
+        self.executed += other.executed;
+        self.jumped += other.jumped;
+        self.argument += other.argument;
+        self.read += other.read;
+        self.written += other.written;
+    }
+}
+
+/// The addresses a machine used, and how.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Coverage {
+    hits: BTreeMap<usize, Hits>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Coverage::default()
+    }
+
+    /// Counts an executed instruction together with the data accesses it made.
+    pub(super) fn record<W>(
+        &mut self,
+        pc: usize,
+        arg_count: usize,
+        jumped: bool,
+        accesses: &[Access<W>],
+    ) {
+        let hits = self.hits.entry(pc).or_default();
+        hits.executed += 1;
+        hits.jumped += jumped as usize;
+        for addr in pc + 1..=pc + arg_count {
+            self.hits.entry(addr).or_default().argument += 1;
+        }
+        for access in accesses {
+            let hits = self.hits.entry(access.addr()).or_default();
+            match access {
+                Access::Read { .. } => hits.read += 1,
+                Access::Write { .. } => hits.written += 1,
+            }
+        }
+    }
+
+    /// How `addr` was used, which is all zero for addresses which were not used at all.
+    pub fn get(&self, addr: usize) -> Hits {
+        self.hits.get(&addr).copied().unwrap_or_default()
+    }
+
+    /// The used addresses in ascending order.
+    pub fn hits(&self) -> impl Iterator<Item = (usize, &Hits)> {
+        self.hits.iter().map(|(&addr, hits)| (addr, hits))
+    }
+
+    /// Adds the counts of another run, e.g. to see what a set of inputs covers together.
+    pub fn merge(&mut self, other: &Coverage) {
+        for (&addr, hits) in &other.hits {
+            self.hits.entry(addr).or_default().merge(hits);
+        }
+    }
+
+    /// Summarizes the coverage of the program in `memory`, which is needed to tell branches from
+    /// other instructions.
+    pub fn report(&self, memory: &Memory) -> Report {
+        let mut report = Report::default();
+        for (addr, hits) in self.hits() {
+            report.instructions += (hits.executed > 0) as usize;
+            report.code_words += hits.is_code() as usize;
+            report.data_words += (!hits.is_code() && hits.is_data()) as usize;
+            report.modified_code_words += (hits.is_code() && hits.written > 0) as usize;
+            if hits.executed > 0 && is_branch(&decode(memory, addr)) {
+                report.branches.push(Branch {
+                    pc: addr,
+                    taken: hits.jumped,
+                    not_taken: hits.executed - hits.jumped,
+                });
+            }
+        }
+        report
+    }
+
+    /// Renders `memory` with the uses of every address, one line per executed instruction and
+    /// one line per word everywhere else, except that stretches of unused zero words are
+    /// collapsed into a single line.
+    ///
+    /// Executed instructions are disassembled and flagged with the uses of all their words, so
+    /// that instructions which were modified stand out.
+    pub fn annotate(&self, memory: &Memory) -> String {
+        let end = self
+            .hits
+            .keys()
+            .next_back()
+            .map_or(0, |&addr| addr + 1)
+            .max(memory.len());
+        let mut s = String::new();
+        let mut addr = 0;
+        while addr < end {
+            let unused = self.next_used(memory, addr, end) - addr;
+            if unused > 1 {
+                let _ = writeln!(s, "{:>6}: ----  ({} unused zero words)", addr, unused);
+                addr += unused;
+                continue;
+            }
+
+            let hits = self.get(addr);
+            let item = match decode(memory, addr) {
+                item @ Item::Instruction { .. } if hits.executed > 0 => item,
+                _ => Item::Data(memory.get(addr)),
+            };
+            let size = item.size();
+
+            let mut flags = hits.flags();
+            for operand in addr + 1..addr + size {
+                let operand = self.get(operand);
+                flags = flags
+                    .chars()
+                    .zip(operand.flags().chars())
+                    .map(|(a, b)| if a == '-' { b } else { a })
+                    .collect();
+            }
+            let _ = write!(s, "{:>6}: {}  {}", addr, flags, item);
+            if hits.executed > 0 {
+                let _ = write!(s, "  ; executed {}", hits.executed);
+                if is_branch(&item) {
+                    let _ = write!(s, ", taken {}", hits.jumped);
+                }
+            } else if hits.is_data() {
+                let _ = write!(s, "  ; read {}, written {}", hits.read, hits.written);
+            }
+            s.push('\n');
+            addr += size;
+        }
+        s
+    }
+
+    /// The first address from `addr` up to `end` which was used or holds a non-zero value,
+    /// jumping over addresses which are neither allocated nor used.
+    fn next_used(&self, memory: &Memory, mut addr: usize, end: usize) -> usize {
+        while addr < end {
+            if self.hits.contains_key(&addr) || memory.get(addr) != 0 {
+                return addr;
+            }
+            let page = memory.next_allocated(addr).unwrap_or(end..end);
+            addr = if page.contains(&addr) {
+                addr + 1
+            } else {
+                let hit = self.hits.range(addr..).next().map_or(end, |(&hit, _)| hit);
+                page.start.min(hit).min(end)
+            };
+        }
+        end
+    }
+}
+
+/// Decodes the item at `addr` without copying more of `memory` than an instruction needs.
+fn decode(memory: &Memory, addr: usize) -> Item {
+    Item::decode(&memory.read_range(addr, addr + MAX_INSTRUCTION_LEN), 0)
+}
+
+/// Whether an item is a conditional jump.
+fn is_branch(item: &Item) -> bool {
+    matches!(
+        item,
+        Item::Instruction {
+            opcode: Opcode::JumpNotZero | Opcode::JumpZero,
+            ..
+        }
+    )
+}
+
+/// An executed conditional jump, see [`Coverage::report`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Branch {
+    pub pc: usize,
+    pub taken: usize,
+    pub not_taken: usize,
+}
+
+impl Branch {
+    /// Whether the branch went both ways.
+    pub fn is_covered(&self) -> bool {
+        self.taken > 0 && self.not_taken > 0
+    }
+}
+
+/// A summary of a [`Coverage`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    /// The number of distinct addresses executed as instructions.
+    pub instructions: usize,
+    /// The number of words which are part of executed instructions.
+    pub code_words: usize,
+    /// The number of words only accessed as data.
+    pub data_words: usize,
+    /// The number of words of executed instructions which were also written.
+    pub modified_code_words: usize,
+    /// The executed conditional jumps in ascending order.
+    pub branches: Vec<Branch>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "instructions: {} ({} words, {} modified)",
+            self.instructions, self.code_words, self.modified_code_words
+        )?;
+        writeln!(f, "data words: {}", self.data_words)?;
+        let covered = self.branches.iter().filter(|b| b.is_covered()).count();
+        writeln!(
+            f,
+            "branches: {} of {} went both ways",
+            covered,
+            self.branches.len()
+        )?;
+        for branch in self.branches.iter().filter(|b| !b.is_covered()) {
+            let way = if branch.taken > 0 {
+                "always taken"
+            } else {
+                "never taken"
+            };
+            writeln!(f, "  {:>6} {}", branch.pc, way)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm::assemble;
+    use crate::intcode::Machine;
+    use std::collections::VecDeque;
+
+    /// Counts down from its input, outputting each value
+    const COUNTDOWN: &str = "
+        IN [12]
+        OUT [12]
+        ADD [12], #-1, [12]
+        JNZ [12], #2
+        HALT
+        DATA 0";
+
+    fn run(source: &str, input: Vec<isize>) -> (Memory, Coverage) {
+        let program = assemble(source).unwrap();
+        let mut machine = Machine::new(program, VecDeque::from(input), Vec::new());
+        machine.start_coverage(Coverage::new());
+        machine.run().unwrap();
+        (machine.memory().clone(), machine.stop_coverage().unwrap())
+    }
+
+    #[test]
+    fn counts_uses() {
+        let (_, coverage) = run(COUNTDOWN, vec![3]);
+
+        let hits = |executed, jumped, argument, read, written| Hits {
+            executed,
+            jumped,
+            argument,
+            read,
+            written,
+        };
+        assert_eq!(hits(1, 0, 0, 0, 0), coverage.get(0));
+        assert_eq!(hits(0, 0, 1, 0, 0), coverage.get(1));
+        assert_eq!(hits(3, 0, 0, 0, 0), coverage.get(2));
+        assert_eq!(hits(3, 2, 0, 0, 0), coverage.get(8));
+        assert_eq!(hits(0, 0, 0, 9, 4), coverage.get(12));
+        assert_eq!(Hits::default(), coverage.get(13));
+    }
+
+    #[test]
+    fn reports_branches() {
+        let (memory, coverage) = run(COUNTDOWN, vec![1]);
+        let report = coverage.report(&memory);
+
+        assert_eq!(
+            (5, 12, 1),
+            (report.instructions, report.code_words, report.data_words)
+        );
+        assert_eq!(
+            vec![Branch {
+                pc: 8,
+                taken: 0,
+                not_taken: 1
+            }],
+            report.branches
+        );
+        assert!(report.to_string().contains("     8 never taken"));
+
+        let (_, mut more) = run(COUNTDOWN, vec![2]);
+        more.merge(&coverage);
+        assert!(more.report(&memory).branches[0].is_covered());
+    }
+
+    #[test]
+    fn annotates_memory() {
+        let (memory, coverage) = run(COUNTDOWN, vec![2]);
+
+        let expected = [
+            "     0: xa--  IN   [12]  ; executed 1",
+            "     2: xa--  OUT  [12]  ; executed 2",
+            "     4: xa--  ADD  [12], #-1, [12]  ; executed 2",
+            "     8: xa--  JNZ  [12], #2  ; executed 2, taken 1",
+            "    11: x---  HALT  ; executed 1",
+            "    12: --rw  DATA 0  ; read 6, written 3",
+            "    13: ----  (1011 unused zero words)",
+        ];
+        assert_eq!(expected.join("\n") + "\n", coverage.annotate(&memory));
+    }
+
+    #[test]
+    fn annotates_far_writes() {
+        let far = 1 << 40;
+        let mut machine = Machine::new(vec![1101, 1, 1, far, 99], VecDeque::new(), ());
+        machine.start_coverage(Coverage::new());
+        machine.run().unwrap();
+        let coverage = machine.stop_coverage().unwrap();
+
+        let expected = [
+            "     0: xa--  ADD  #1, #1, [1099511627776]  ; executed 1".to_string(),
+            "     4: x---  HALT  ; executed 1".to_string(),
+            "     5: ----  (1099511627771 unused zero words)".to_string(),
+            format!("{}: ---w  DATA 2  ; read 0, written 1", far),
+            format!("{}: ----  (1023 unused zero words)", far + 1),
+        ];
+        assert_eq!(
+            expected.join("\n") + "\n",
+            coverage.annotate(machine.memory())
+        );
+        assert_eq!(2, coverage.report(machine.memory()).instructions);
+    }
+}
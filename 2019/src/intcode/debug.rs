@@ -0,0 +1,329 @@
+//! Debugger for intcode machines.
+//!
+//! A [`Debugger`] drives a [`Machine`] one step at a time, stopping at breakpoints (before the
+//! instruction at the address executes) and at watchpoints (after an instruction accessed the
+//! watched address). It keeps a history of recent instructions, so that it can also step
+//! backwards. The `intcode-debug` binary provides a REPL on top of it.
+
+use super::disasm::Item;
+use super::history::Entry;
+use super::{Access, History, Machine, State};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+
+/// The number of instructions the debugger can step back.
+const HISTORY: usize = 100_000;
+
+/// The most items [`Debugger::disassemble`] decodes and words [`Debugger::dump`] renders at once.
+pub const MAX_LISTING: usize = 4096;
+
+/// The kind of access which triggers a watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watch {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Watch {
+    fn matches(self, access: &Access) -> bool {
+        matches!(
+            (self, access),
+            (Watch::ReadWrite, _)
+                | (Watch::Read, Access::Read { .. })
+                | (Watch::Write, Access::Write { .. })
+        )
+    }
+}
+
+/// Why the debugger stopped.
+#[derive(Debug)]
+pub enum Stop {
+    /// The machine reached a breakpoint, the instruction at it has not been executed yet.
+    Breakpoint(usize),
+    /// The instruction at `pc` made an access which triggered a watchpoint.
+    Watchpoint { pc: usize, access: Access },
+    /// The machine stopped by itself.
+    Machine(State),
+}
+
+#[derive(Debug)]
+pub struct Debugger {
+    machine: Machine,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeMap<usize, Watch>,
+}
+
+impl Debugger {
+    pub fn new(mut machine: Machine) -> Self {
+        machine.record_accesses(true);
+        machine.start_history(History::new(HISTORY));
+        Debugger {
+            machine,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeMap::new(),
+        }
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    pub fn machine_mut(&mut self) -> &mut Machine {
+        &mut self.machine
+    }
+
+    pub fn into_machine(mut self) -> Machine {
+        self.machine.record_accesses(false);
+        self.machine
+    }
+
+    /// Adds a breakpoint, returning false if there already was one at `addr`.
+    pub fn add_breakpoint(&mut self, addr: usize) -> bool {
+        self.breakpoints.insert(addr)
+    }
+
+    /// Removes a breakpoint, returning false if there was none at `addr`.
+    pub fn remove_breakpoint(&mut self, addr: usize) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Watches an address, replacing any previous watchpoint on it.
+    pub fn add_watchpoint(&mut self, addr: usize, watch: Watch) {
+        self.watchpoints.insert(addr, watch);
+    }
+
+    /// Removes a watchpoint, returning false if there was none on `addr`.
+    pub fn remove_watchpoint(&mut self, addr: usize) -> bool {
+        self.watchpoints.remove(&addr).is_some()
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = (usize, Watch)> + '_ {
+        self.watchpoints.iter().map(|(&addr, &watch)| (addr, watch))
+    }
+
+    /// Queues values on the input of the machine.
+    pub fn push_input<T: IntoIterator<Item = isize>>(&mut self, values: T) {
+        self.machine.input_mut().extend(values);
+    }
+
+    /// Removes and returns the output the machine produced so far.
+    pub fn take_output(&mut self) -> Vec<isize> {
+        self.machine.output_mut().drain(..).collect()
+    }
+
+    /// Executes a single instruction, ignoring breakpoints.
+    pub fn step(&mut self) -> Stop {
+        let pc = self.machine.pc();
+        let state = self.machine.step();
+        if let State::Faulted(_) = state {
+            return Stop::Machine(state);
+        }
+
+        let watchpoints = &self.watchpoints;
+        let triggered = self.machine.accesses().iter().find(|access| {
+            watchpoints
+                .get(&access.addr())
+                .is_some_and(|watch| watch.matches(access))
+        });
+        match triggered {
+            Some(&access) => Stop::Watchpoint { pc, access },
+            None => Stop::Machine(state),
+        }
+    }
+
+    /// Executes instructions until a breakpoint or watchpoint is hit or the machine stops by
+    /// itself, i.e. it needs input, is blocked, halts or faults.
+    ///
+    /// A breakpoint at the current pc does not stop the debugger, so that it can continue after
+    /// stopping at it.
+    pub fn resume(&mut self) -> Stop {
+        loop {
+            match self.step() {
+                Stop::Machine(State::Running) | Stop::Machine(State::Output(_)) => {}
+                stop => break stop,
+            }
+            if self.breakpoints.contains(&self.machine.pc()) {
+                break Stop::Breakpoint(self.machine.pc());
+            }
+        }
+    }
+
+    /// Undoes the last instruction, returning whether there was one to undo.
+    ///
+    /// The input it read is queued again, and the output it wrote is removed unless it was
+    /// already taken.
+    pub fn step_back(&mut self) -> bool {
+        match self.machine.step_back() {
+            Some(entry) => {
+                self.undo_io(entry);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Steps back until the instruction at `pc` is the next to execute again, see
+    /// [`Machine::run_back_to`], returning the number of undone instructions.
+    pub fn run_back_to(&mut self, pc: usize) -> usize {
+        let undone = self.machine.run_back_to(pc);
+        let count = undone.len();
+        undone.into_iter().for_each(|entry| self.undo_io(entry));
+        count
+    }
+
+    /// The step and pc of the most recent instruction which wrote `addr`.
+    pub fn last_write(&self, addr: usize) -> Option<(usize, usize)> {
+        let entry = self.machine.history()?.last_write(addr)?;
+        Some((entry.step, entry.pc))
+    }
+
+    fn undo_io(&mut self, entry: Entry) {
+        if let Some(value) = entry.input {
+            self.machine.input_mut().push_front(value);
+        }
+        // Outputs are undone newest first, hence the newest pending one belongs to the entry
+        if entry.output.is_some() {
+            self.machine.output_mut().pop_back();
+        }
+    }
+
+    /// Decodes up to `count` items starting at `addr`, stopping after [`MAX_LISTING`] items or
+    /// at the end of the address space.
+    pub fn disassemble(&self, addr: usize, count: usize) -> Vec<(usize, Item)> {
+        let count = count.min(MAX_LISTING);
+        // No item is longer than four words
+        let end = addr.saturating_add(4 * count);
+        let window = self.machine.memory().read_range(addr, end);
+        let mut items = Vec::with_capacity(count);
+        let mut offset = 0;
+        for _ in 0..count {
+            let item_addr = match addr.checked_add(offset) {
+                Some(item_addr) if item_addr < end => item_addr,
+                _ => break,
+            };
+            let item = Item::decode(&window, offset);
+            let size = item.size();
+            items.push((item_addr, item));
+            offset += size;
+        }
+        items
+    }
+
+    /// Renders up to `len` words of memory starting at `start`, eight words per line, stopping
+    /// after [`MAX_LISTING`] words or at the end of the address space.
+    pub fn dump(&self, start: usize, len: usize) -> String {
+        let end = start.saturating_add(len.min(MAX_LISTING));
+        let mut s = String::new();
+        for row in (start..end).step_by(8) {
+            let _ = write!(s, "{:>6}:", row);
+            for addr in row..row.saturating_add(8).min(end) {
+                let _ = write!(s, " {}", self.machine.peek(addr));
+            }
+            s.push('\n');
+        }
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm::assemble;
+    use crate::intcode::{Error, Fault};
+    use std::collections::VecDeque;
+
+    fn debugger(source: &str) -> Debugger {
+        let program = assemble(source).unwrap();
+        Debugger::new(Machine::new(program, VecDeque::new(), VecDeque::new()))
+    }
+
+    #[test]
+    fn stops_before_breakpoint() {
+        let mut debugger = debugger("ADD #1, #2, [9]\nOUT [9]\nHALT");
+        debugger.add_breakpoint(4);
+
+        assert!(matches!(debugger.resume(), Stop::Breakpoint(4)));
+        assert!(debugger.take_output().is_empty());
+        assert!(matches!(debugger.resume(), Stop::Machine(State::Halted(_))));
+        assert_eq!(vec![3], debugger.take_output());
+    }
+
+    #[test]
+    fn watchpoints_trigger_on_matching_access() {
+        let mut debugger = debugger("IN [9]\nOUT [9]\nHALT");
+        debugger.add_watchpoint(9, Watch::Read);
+
+        assert!(matches!(
+            debugger.resume(),
+            Stop::Machine(State::NeedsInput)
+        ));
+        debugger.push_input(vec![5]);
+        match debugger.resume() {
+            Stop::Watchpoint { pc, access } => {
+                assert_eq!(2, pc);
+                assert_eq!(Access::Read { addr: 9, value: 5 }, access);
+            }
+            stop => panic!("unexpected stop {:?}", stop),
+        }
+    }
+
+    #[test]
+    fn steps_back_over_io() {
+        let mut debugger = debugger("IN [9]\nOUT [9]\nOUT #7\nHALT");
+        debugger.push_input(vec![5]);
+        debugger.resume();
+        assert_eq!(vec![5, 7], debugger.take_output());
+
+        assert_eq!(Some((0, 0)), debugger.last_write(9));
+        assert_eq!(4, debugger.run_back_to(0));
+        assert_eq!(0, debugger.machine().peek(9));
+        assert_eq!(vec![5], Vec::from(debugger.machine().input().clone()));
+        assert!(!debugger.step_back());
+
+        debugger.resume();
+        assert_eq!(vec![5, 7], debugger.take_output());
+    }
+
+    #[test]
+    fn reports_faults_after_watched_reads() {
+        let mut debugger = Debugger::new(Machine::new(
+            vec![1001, 5, 1, -1, 99, 7],
+            VecDeque::new(),
+            VecDeque::new(),
+        ));
+        debugger.add_watchpoint(5, Watch::Read);
+
+        assert!(matches!(
+            debugger.resume(),
+            Stop::Machine(State::Faulted(Fault {
+                error: Error::NegativeAddress { addr: -1 },
+                ..
+            }))
+        ));
+        assert!(debugger.machine().accesses().is_empty());
+    }
+
+    #[test]
+    fn dumps_memory() {
+        let debugger = debugger("DATA 1, 2, 3");
+
+        assert_eq!("     1: 2 3 0\n", debugger.dump(1, 3));
+    }
+
+    #[test]
+    fn stops_listings_at_end_of_address_space() {
+        let debugger = debugger("DATA 1, 2, 3");
+
+        assert_eq!(
+            format!("{:>6}: 0\n", usize::MAX - 1),
+            debugger.dump(usize::MAX - 1, 16)
+        );
+        assert_eq!(1, debugger.disassemble(usize::MAX - 1, 2).len());
+        assert_eq!(MAX_LISTING, debugger.disassemble(0, usize::MAX).len());
+    }
+}
@@ -0,0 +1,220 @@
+//! Disassembler for intcode programs.
+//!
+//! Programs are decoded linearly from address 0. Every word which is not a canonically encoded
+//! instruction with all its operands in bounds becomes `DATA`, and static jump targets which land
+//! on a decoded item get a synthesized label.
+
+use super::{Instruction, Mode, Opcode};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// An operand of a decoded instruction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Operand {
+    pub mode: Mode,
+    pub value: isize,
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.mode {
+            Mode::Position => write!(f, "[{}]", self.value),
+            Mode::Immidiate => write!(f, "#{}", self.value),
+            Mode::Relative if self.value < 0 => write!(f, "rb-{}", -(self.value as i128)),
+            Mode::Relative => write!(f, "rb+{}", self.value),
+        }
+    }
+}
+
+/// A decoded word or instruction.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Item {
+    Instruction {
+        opcode: Opcode,
+        operands: Vec<Operand>,
+    },
+    Data(isize),
+}
+
+impl Item {
+    /// Decodes the item at `addr`, where every address past the end of `memory` holds zero.
+    ///
+    /// Falls back to data for words which are not the canonical encoding of an instruction (see
+    /// [`Instruction::encode`]) and for instructions whose operands run past the end of `memory`.
+    pub fn decode(memory: &[isize], addr: usize) -> Item {
+        let word = memory.get(addr).copied().unwrap_or(0);
+        let instr = match Instruction::try_from(word) {
+            Ok(instr) if instr.encode() == word => instr,
+            _ => return Item::Data(word),
+        };
+
+        let arg_count = instr.opcode.arg_count();
+        match memory.get(addr + 1..addr + 1 + arg_count) {
+            Some(args) => Item::Instruction {
+                opcode: instr.opcode,
+                operands: args
+                    .iter()
+                    .zip(&instr.modes)
+                    .map(|(&value, &mode)| Operand { mode, value })
+                    .collect(),
+            },
+            None => Item::Data(word),
+        }
+    }
+
+    /// The number of words the item occupies.
+    pub fn size(&self) -> usize {
+        match self {
+            Item::Instruction { operands, .. } => 1 + operands.len(),
+            Item::Data(_) => 1,
+        }
+    }
+
+    /// The address this item jumps to, if it is a jump with an immediate target.
+    pub fn jump_target(&self) -> Option<usize> {
+        match self {
+            Item::Instruction {
+                opcode: Opcode::JumpNotZero,
+                operands,
+            }
+            | Item::Instruction {
+                opcode: Opcode::JumpZero,
+                operands,
+            } => match operands[1] {
+                Operand {
+                    mode: Mode::Immidiate,
+                    value,
+                } if value >= 0 => Some(value as usize),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn fmt_with_labels(
+        &self,
+        f: &mut fmt::Formatter,
+        labels: &BTreeMap<usize, String>,
+    ) -> fmt::Result {
+        match self {
+            Item::Instruction { opcode, operands } => {
+                write!(f, "{:<4}", opcode.mnemonic())?;
+                let target = self.jump_target().and_then(|addr| labels.get(&addr));
+                for (i, operand) in operands.iter().enumerate() {
+                    f.write_str(if i == 0 { " " } else { ", " })?;
+                    match target {
+                        Some(label) if i == 1 => write!(f, "#{}", label)?,
+                        _ => write!(f, "{}", operand)?,
+                    }
+                }
+                Ok(())
+            }
+            Item::Data(value) => write!(f, "DATA {}", value),
+        }
+    }
+}
+
+impl fmt::Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_with_labels(f, &BTreeMap::new())
+    }
+}
+
+/// A decoded program, see [`disassemble`].
+#[derive(Debug, Clone)]
+pub struct Disassembly {
+    items: Vec<(usize, Item)>,
+    labels: BTreeMap<usize, String>,
+}
+
+impl Disassembly {
+    /// The decoded items together with their addresses.
+    pub fn items(&self) -> &[(usize, Item)] {
+        &self.items
+    }
+
+    /// The label synthesized for `addr`, if any.
+    pub fn label(&self, addr: usize) -> Option<&str> {
+        self.labels.get(&addr).map(String::as_str)
+    }
+}
+
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self
+            .items
+            .last()
+            .map_or(1, |(addr, _)| addr.to_string().len());
+
+        for (addr, item) in &self.items {
+            if let Some(label) = self.labels.get(addr) {
+                writeln!(f, "{}:", label)?;
+            }
+            write!(f, "{:>width$}: ", addr, width = width)?;
+            item.fmt_with_labels(f, &self.labels)?;
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Disassembles a program.
+pub fn disassemble(program: &[isize]) -> Disassembly {
+    let mut items = Vec::new();
+    let mut addr = 0;
+    while addr < program.len() {
+        let item = Item::decode(program, addr);
+        let size = item.size();
+        items.push((addr, item));
+        addr += size;
+    }
+
+    let labels = items
+        .iter()
+        .filter_map(|(_, item)| item.jump_target())
+        .filter(|target| {
+            items
+                .binary_search_by_key(target, |&(addr, _)| addr)
+                .is_ok()
+        })
+        .map(|target| (target, format!("L{}", target)))
+        .collect();
+
+    Disassembly { items, labels }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operand_modes() {
+        let listing = disassemble(&[21101, 5, -3, 7, 99]).to_string();
+
+        assert_eq!("0: ADD  #5, #-3, rb+7\n4: HALT\n", listing);
+    }
+
+    #[test]
+    fn labels_static_jump_targets() {
+        // Jumps over the data word to halt
+        let listing = disassemble(&[1105, 1, 4, 42, 99]).to_string();
+
+        assert_eq!("0: JNZ  #1, #L4\n3: DATA 42\nL4:\n4: HALT\n", listing);
+    }
+
+    #[test]
+    fn falls_back_to_data() {
+        let disassembly = disassemble(&[1099, 1, 0, 1]);
+
+        assert_eq!(
+            &[
+                (0, Item::Data(1099)),
+                (1, Item::Data(1)),
+                (2, Item::Data(0)),
+                (3, Item::Data(1))
+            ],
+            disassembly.items()
+        );
+    }
+}
@@ -0,0 +1,435 @@
+//! A faster engine for running intcode machines.
+//!
+//! [`Machine::step`] decodes the instruction at pc on every step and resolves each argument from
+//! the words following it. A [`FastMachine`] instead decodes each instruction once, caching the
+//! opcode together with its resolved arguments, and drops cached instructions whenever the
+//! program writes over them. It behaves exactly like the reference interpreter, which it falls
+//! back to for anything it does not cache: instructions which fault while decoding or use
+//! registered extension opcodes, code beyond the allocated memory, steps which record accesses or
+//! traces, and machines whose registry does not start from the standard operations.
+
+use super::{
+    to_addr, Access, AddressOverflow, Error, Fault, Input, Instruction, Machine, Memory, Mode,
+    Opcode, Output, Overflow, Result, State, Word, MAX_INSTRUCTION_LEN,
+};
+use snafu::OptionExt as _;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::task::Poll;
+
+/// What executing a cached instruction did.
+#[derive(Debug)]
+enum Status<W> {
+    Advance(usize),
+    Jump(usize),
+    /// Advance after writing a value to the output
+    Emit(usize, W),
+    /// The instruction could not execute yet
+    Wait(State<W>),
+    Halt,
+}
+
+/// An argument which is read.
+#[derive(Debug, Clone)]
+enum Arg<W> {
+    Absolute(usize),
+    Relative(isize),
+    Immediate(W),
+}
+
+/// An argument which is written.
+#[derive(Debug, Clone, Copy)]
+enum Target {
+    Absolute(usize),
+    Relative(isize),
+}
+
+#[derive(Debug, Clone)]
+struct Decoded<W> {
+    opcode: Opcode,
+    /// The arguments which are read, unused ones are immediate zeroes.
+    args: [Arg<W>; 2],
+    /// Where the result is written, unused unless the instruction writes.
+    target: Target,
+    len: usize,
+}
+
+impl<W: Word> Decoded<W> {
+    /// Decodes the instruction at `pc`, or `None` if it would fault.
+    fn decode(memory: &Memory<W>, pc: usize) -> Option<Self> {
+        let instr = Instruction::try_from(memory.get(pc).to_isize()?).ok()?;
+        let count = instr.opcode.arg_count();
+        let writes = match instr.opcode {
+            Opcode::Add | Opcode::Mul | Opcode::LessThan | Opcode::Equal => Some(2),
+            Opcode::Input => Some(0),
+            _ => None,
+        };
+
+        let mut args = [Arg::Immediate(W::zero()), Arg::Immediate(W::zero())];
+        let mut target = Target::Absolute(0);
+        for (i, &mode) in instr.modes[..count].iter().enumerate() {
+            let addr = pc + 1 + i;
+            let word = memory.get(addr);
+            if writes == Some(i) {
+                target = match mode {
+                    Mode::Position => Target::Absolute(to_addr(&word).ok()?),
+                    Mode::Relative => Target::Relative(word.to_isize()?),
+                    // Writes the argument itself, just like the reference interpreter
+                    Mode::Immidiate => Target::Absolute(addr),
+                };
+            } else {
+                args[i] = match mode {
+                    Mode::Position => Arg::Absolute(to_addr(&word).ok()?),
+                    Mode::Relative => Arg::Relative(word.to_isize()?),
+                    Mode::Immidiate => Arg::Immediate(word),
+                };
+            }
+        }
+
+        Some(Decoded {
+            opcode: instr.opcode,
+            args,
+            target,
+            len: count + 1,
+        })
+    }
+}
+
+/// A machine which caches decoded instructions, see the [module documentation](self).
+#[derive(Debug, Clone)]
+pub struct FastMachine<I = VecDeque<isize>, O = VecDeque<isize>, W = isize> {
+    machine: Machine<I, O, W>,
+    cache: Vec<Option<Decoded<W>>>,
+}
+
+impl<I, O, W: Word> FastMachine<I, O, W> {
+    pub fn new(machine: Machine<I, O, W>) -> Self {
+        FastMachine {
+            machine,
+            cache: Vec::new(),
+        }
+    }
+
+    pub fn machine(&self) -> &Machine<I, O, W> {
+        &self.machine
+    }
+
+    /// Gives access to the machine, which drops every cached instruction since its memory might
+    /// be changed.
+    pub fn machine_mut(&mut self) -> &mut Machine<I, O, W> {
+        self.cache.clear();
+        &mut self.machine
+    }
+
+    pub fn into_machine(self) -> Machine<I, O, W> {
+        self.machine
+    }
+
+    /// Drops the cached instructions which contain `addr`, which start at most the length of the
+    /// longest instruction before it.
+    fn invalidate(&mut self, addr: usize) {
+        let start = addr.saturating_sub(MAX_INSTRUCTION_LEN - 1);
+        let end = self.cache.len().min(addr + 1);
+        for slot in self.cache.get_mut(start..end).unwrap_or_default() {
+            *slot = None;
+        }
+    }
+}
+
+impl<I: Input<W>, O: Output<W>, W: Word> FastMachine<I, O, W> {
+    /// Executes a single instruction, see [`Machine::step`].
+    pub fn step(&mut self) -> State<W> {
+        let machine = &mut self.machine;
+        let pc = machine.pc;
+        let standard = machine
+            .registry
+            .as_ref()
+            .is_none_or(|registry| registry.is_standard());
+        // Sessions record I/O, which only the reference interpreter does
+        if machine.logging() || machine.session.is_some() || !standard {
+            return self.fallback();
+        }
+        if let Err(e) = machine.check_budget() {
+            return State::Faulted(machine.fault(e));
+        }
+        if pc >= self.cache.len() && pc < machine.memory.len() {
+            self.cache.resize(machine.memory.len(), None);
+        }
+        let decoded = match self.cache.get_mut(pc) {
+            Some(slot) => {
+                if slot.is_none() {
+                    *slot = Decoded::decode(&machine.memory, pc);
+                }
+                slot.as_ref()
+            }
+            None => None,
+        };
+        let decoded = match decoded {
+            Some(decoded) => decoded,
+            None => return self.fallback(),
+        };
+
+        let (status, written) = match execute(machine, decoded) {
+            Ok(result) => result,
+            Err(e) => return State::Faulted(machine.fault(e)),
+        };
+        if let Some(addr) = written {
+            self.invalidate(addr);
+        }
+
+        let machine = &mut self.machine;
+        let state = match status {
+            Status::Advance(incr) => {
+                machine.pc += incr;
+                State::Running
+            }
+            Status::Jump(new_pc) => {
+                machine.pc = new_pc;
+                State::Running
+            }
+            Status::Emit(incr, value) => {
+                machine.pc += incr;
+                State::Output(value)
+            }
+            Status::Wait(state) => return state,
+            Status::Halt => State::Halted(machine.peek(0)),
+        };
+        machine.steps += 1;
+        state
+    }
+
+    /// Executes a step on the reference interpreter, dropping the cached instructions it wrote
+    /// over, e.g. from a registered extension opcode.
+    fn fallback(&mut self) -> State<W> {
+        let recording = self.machine.record_accesses;
+        self.machine.record_accesses = true;
+        let state = self.machine.step();
+        self.machine.record_accesses = recording;
+
+        let written: Vec<_> = self
+            .machine
+            .accesses
+            .iter()
+            .filter_map(|access| match access {
+                Access::Write { addr, .. } => Some(*addr),
+                Access::Read { .. } => None,
+            })
+            .collect();
+        if !self.machine.logging() {
+            self.machine.accesses.clear();
+        }
+        for addr in written {
+            self.invalidate(addr);
+        }
+        state
+    }
+
+    /// Executes instructions until the machine outputs a value, has to wait, halts or faults,
+    /// see [`Machine::run_until_event`].
+    pub fn run_until_event(&mut self) -> State<W> {
+        loop {
+            match self.step() {
+                State::Running => {}
+                state => break state,
+            }
+        }
+    }
+
+    /// Runs the machine until it halts, see [`Machine::run`].
+    pub fn run(&mut self) -> Result<W, Fault> {
+        loop {
+            match self.run_until_event() {
+                State::Running | State::Output(_) => {}
+                State::NeedsInput => break Err(self.machine.fault(Error::InputUnavailable)),
+                State::OutputBlocked => break Err(self.machine.fault(Error::OutputFull)),
+                State::Halted(value) => break Ok(value),
+                State::Faulted(e) => break Err(e),
+            }
+        }
+    }
+}
+
+/// Executes a decoded instruction at pc, returning the address it wrote, if any.
+fn execute<I: Input<W>, O: Output<W>, W: Word>(
+    machine: &mut Machine<I, O, W>,
+    decoded: &Decoded<W>,
+) -> Result<(Status<W>, Option<usize>)> {
+    let next = decoded.len;
+    let [lhs, rhs] = &decoded.args;
+    match decoded.opcode {
+        Opcode::Add => {
+            let value = machine
+                .overflow
+                .add(&load(machine, lhs)?, &load(machine, rhs)?)
+                .context(Overflow { pc: machine.pc })?;
+            let addr = store(machine, decoded.target, value)?;
+            Ok((Status::Advance(next), Some(addr)))
+        }
+        Opcode::Mul => {
+            let value = machine
+                .overflow
+                .mul(&load(machine, lhs)?, &load(machine, rhs)?)
+                .context(Overflow { pc: machine.pc })?;
+            let addr = store(machine, decoded.target, value)?;
+            Ok((Status::Advance(next), Some(addr)))
+        }
+        Opcode::Input => {
+            // Input is only consumed once it is certain to be stored
+            let addr = target_addr(machine, decoded.target)?;
+            machine.memory.check(addr)?;
+            match machine.input.read()? {
+                Poll::Ready(value) => {
+                    machine.memory.set(addr, value)?;
+                    Ok((Status::Advance(next), Some(addr)))
+                }
+                Poll::Pending => Ok((Status::Wait(State::NeedsInput), None)),
+            }
+        }
+        Opcode::Output => {
+            let value = load(machine, lhs)?;
+            match machine.output.write(value.clone())? {
+                Poll::Ready(()) => Ok((Status::Emit(next, value), None)),
+                Poll::Pending => Ok((Status::Wait(State::OutputBlocked), None)),
+            }
+        }
+        Opcode::JumpNotZero | Opcode::JumpZero => {
+            let jump = load(machine, lhs)?.is_zero() == (decoded.opcode == Opcode::JumpZero);
+            if jump {
+                Ok((Status::Jump(to_addr(&load(machine, rhs)?)?), None))
+            } else {
+                Ok((Status::Advance(next), None))
+            }
+        }
+        Opcode::LessThan | Opcode::Equal => {
+            let (lhs, rhs) = (load(machine, lhs)?, load(machine, rhs)?);
+            let value = match decoded.opcode {
+                Opcode::LessThan => lhs < rhs,
+                _ => lhs == rhs,
+            };
+            let addr = store(machine, decoded.target, W::from_isize(value as isize))?;
+            Ok((Status::Advance(next), Some(addr)))
+        }
+        Opcode::OffsetRBase => {
+            let offset = load(machine, lhs)?.to_isize();
+            machine.relative_base = offset
+                .and_then(|offset| machine.relative_base.checked_add(offset))
+                .context(AddressOverflow)?;
+            Ok((Status::Advance(next), None))
+        }
+        Opcode::Halt => Ok((Status::Halt, None)),
+    }
+}
+
+fn relative<I, O, W>(machine: &Machine<I, O, W>, offset: isize) -> Result<usize> {
+    offset
+        .checked_add(machine.relative_base)
+        .context(AddressOverflow)
+        .and_then(|addr| to_addr(&addr))
+}
+
+fn load<I, O, W: Word>(machine: &Machine<I, O, W>, arg: &Arg<W>) -> Result<W> {
+    match arg {
+        Arg::Absolute(addr) => Ok(machine.memory.get(*addr)),
+        Arg::Relative(offset) => Ok(machine.memory.get(relative(machine, *offset)?)),
+        Arg::Immediate(value) => Ok(value.clone()),
+    }
+}
+
+fn target_addr<I, O, W>(machine: &Machine<I, O, W>, target: Target) -> Result<usize> {
+    match target {
+        Target::Absolute(addr) => Ok(addr),
+        Target::Relative(offset) => relative(machine, offset),
+    }
+}
+
+fn store<I, O, W: Word>(machine: &mut Machine<I, O, W>, target: Target, value: W) -> Result<usize> {
+    let addr = target_addr(machine, target)?;
+    machine.memory.set(addr, value)?;
+    Ok(addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::Program;
+
+    type Fast = FastMachine<VecDeque<isize>, Vec<isize>>;
+
+    /// Runs a program on both engines, checking they agree on everything observable.
+    fn differential(program: Vec<isize>, input: Vec<isize>) -> (Result<isize, Fault>, Fast) {
+        let mut reference =
+            Machine::new(program.clone(), VecDeque::from(input.clone()), Vec::new());
+        let mut fast = FastMachine::new(Machine::new(program, VecDeque::from(input), Vec::new()));
+
+        let expected = reference.run();
+        let result = fast.run();
+        assert_eq!(format!("{:?}", expected), format!("{:?}", result));
+        assert_eq!(reference.input(), fast.machine().input());
+        assert_eq!(reference.output(), fast.machine().output());
+        assert_eq!(reference.steps(), fast.machine().steps());
+        assert_eq!(reference.pc(), fast.machine().pc());
+        assert_eq!(reference.memory(), fast.machine().memory());
+        (result, fast)
+    }
+
+    #[test]
+    fn agrees_with_reference() {
+        let input = include_str!("../../input/2019/day9.txt");
+        let program = input.parse::<Program>().unwrap().into_words();
+
+        differential(program.clone(), vec![1]).0.unwrap();
+        differential(program, vec![2]).0.unwrap();
+    }
+
+    #[test]
+    fn invalidates_modified_instructions() {
+        // Adds to 20 what it adds to its own immediate argument, until 20 reaches 10
+        let program = vec![
+            1001, 20, 1, 20, 1001, 2, 1, 2, 1007, 20, 10, 21, 1005, 21, 0, 99,
+        ];
+        let (result, fast) = differential(program, vec![]);
+        assert_eq!(1001, result.unwrap());
+        assert_eq!(10, fast.machine().peek(20));
+
+        // Overwrites an instruction it already executed with a halt
+        let program = vec![1001, 20, 1, 20, 1101, 0, 99, 0, 1105, 1, 0];
+        let (result, fast) = differential(program, vec![]);
+        assert_eq!(99, result.unwrap());
+        assert_eq!(1, fast.machine().peek(20));
+    }
+
+    #[test]
+    fn faults_like_reference() {
+        assert!(matches!(
+            differential(vec![1, 9, 10, -1, 99], vec![]).0,
+            Err(Fault {
+                error: Error::NegativeAddress { addr: -1 },
+                ..
+            })
+        ));
+        assert!(matches!(
+            differential(vec![1101, 20, 22, 4, 0], vec![]).0,
+            Err(Fault {
+                error: Error::InvalidOpcode { instr: 42, .. },
+                ..
+            })
+        ));
+        assert!(matches!(
+            differential(vec![3, 0, 99], vec![]).0,
+            Err(Fault {
+                error: Error::InputUnavailable,
+                ..
+            })
+        ));
+
+        let (result, fast) = differential(vec![203, -5, 99], vec![7, 8]);
+        assert!(matches!(
+            result,
+            Err(Fault {
+                error: Error::NegativeAddress { addr: -5 },
+                ..
+            })
+        ));
+        assert_eq!(&VecDeque::from(vec![7, 8]), fast.machine().input());
+    }
+}
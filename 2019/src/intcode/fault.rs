@@ -0,0 +1,185 @@
+//! Faults of intcode machines, with the context needed to make sense of them.
+//!
+//! A [`Fault`] wraps the [`Error`] which stopped a machine together with where it stopped: the
+//! pc, the relative base, the number of steps executed and the memory around pc, disassembled
+//! like [`disasm`](super::disasm) does. Its `Display` output is a self-contained report:
+//!
+//! ```text
+//! invalid opcode in instruction '42' at pc 4 (relative base 0, 1 steps)
+//!        0: ADD  #20, #22, [4]
+//!     => 4: DATA 42
+//! ```
+
+use super::disasm::Item;
+use super::{Error, Memory, Word};
+use std::fmt;
+
+/// The number of words shown before and after pc.
+const WINDOW: usize = 8;
+
+/// An error together with the state of the machine it occurred in.
+#[derive(Debug)]
+pub struct Fault {
+    pub error: Error,
+    /// The address of the instruction which failed.
+    pub pc: usize,
+    pub relative_base: isize,
+    /// The number of instructions executed before the failing one.
+    pub steps: usize,
+    window: Vec<(usize, String)>,
+}
+
+impl Fault {
+    /// Captures the context of an error raised while executing the instruction at `pc`.
+    pub(super) fn capture<W: Word>(
+        error: Error,
+        pc: usize,
+        relative_base: isize,
+        steps: usize,
+        memory: &Memory<W>,
+    ) -> Self {
+        Fault {
+            error,
+            pc,
+            relative_base,
+            steps,
+            window: window(memory, pc),
+        }
+    }
+
+    /// Disassembled lines of the memory around pc, together with their addresses.
+    pub fn window(&self) -> &[(usize, String)] {
+        &self.window
+    }
+
+    /// Discards the context, returning the error.
+    pub fn into_error(self) -> Error {
+        self.error
+    }
+}
+
+/// Disassembles the memory around `pc`.
+///
+/// Decoding starts a few words before pc, where an item which would overlap pc is shown as data
+/// instead so the instruction at pc is always decoded. Words which do not fit in an `isize` are
+/// shown as data as well, and zeros at the end of the window are left out.
+fn window<W: Word>(memory: &Memory<W>, pc: usize) -> Vec<(usize, String)> {
+    let start = pc.saturating_sub(WINDOW);
+    let mut end = pc.saturating_add(WINDOW);
+    while end > pc.saturating_add(1) && memory.get(end - 1).to_isize() == Some(0) {
+        end -= 1;
+    }
+    let words = memory.read_range(start, end.saturating_add(3));
+
+    let mut lines = Vec::new();
+    let mut addr = start;
+    while addr < end {
+        let word = &words[addr - start];
+        let fitting: Vec<isize> = words[addr - start..]
+            .iter()
+            .map_while(Word::to_isize)
+            .collect();
+        let line = match Item::decode(&fitting, 0) {
+            _ if fitting.is_empty() => (format!("DATA {}", word), 1),
+            item if addr < pc && addr + item.size() > pc => (format!("DATA {}", word), 1),
+            item => (item.to_string(), item.size()),
+        };
+        lines.push((addr, line.0));
+        addr += line.1;
+    }
+    lines
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at pc {} (relative base {}, {} steps)",
+            self.error, self.pc, self.relative_base, self.steps
+        )?;
+        let width = self
+            .window
+            .last()
+            .map_or(1, |(addr, _)| addr.to_string().len());
+        for (addr, line) in &self.window {
+            let marker = if *addr == self.pc { "=>" } else { "  " };
+            write!(
+                f,
+                "\n    {} {:>width$}: {}",
+                marker,
+                addr,
+                line,
+                width = width
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Fault {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Machine, State};
+    use super::*;
+    use std::collections::VecDeque;
+
+    #[test]
+    fn reports_context() {
+        // Moves the relative base below zero, then writes relative to it
+        let mut machine = Machine::new(vec![109, -5, 21101, 1, 2, 0, 99], VecDeque::new(), ());
+        let fault = machine.run().unwrap_err();
+
+        assert!(matches!(fault.error, Error::NegativeAddress { addr: -5 }));
+        assert_eq!((2, -5, 1), (fault.pc, fault.relative_base, fault.steps));
+        assert_eq!(
+            "address -5 is negative at pc 2 (relative base -5, 1 steps)\n\
+             \x20      0: ARB  #-5\n\
+             \x20   => 2: ADD  #1, #2, rb+0\n\
+             \x20      6: HALT",
+            fault.to_string()
+        );
+    }
+
+    #[test]
+    fn aligns_window_on_pc() {
+        // Jumps into the middle of what decodes as an addition
+        let mut program = vec![1105, 1, 12, 0, 0, 0, 0, 0, 0, 0, 1, 0, 42];
+        program.resize(20, 0);
+        let mut machine = Machine::new(program, VecDeque::new(), ());
+        let fault = match machine.run_until_event() {
+            State::Faulted(fault) => fault,
+            state => panic!("unexpected state {:?}", state),
+        };
+
+        let addrs: Vec<usize> = fault.window().iter().map(|(addr, _)| *addr).collect();
+        assert_eq!(vec![4, 5, 6, 7, 8, 9, 10, 11, 12], addrs);
+        assert_eq!((10, "DATA 1".to_string()), fault.window()[6]);
+        assert_eq!((12, "DATA 42".to_string()), fault.window()[8]);
+    }
+
+    #[test]
+    fn shows_large_words_as_data() {
+        let huge = i128::from(i64::MAX) * 4;
+        let program = vec![1101, 1, huge, 5, 42];
+        let mut machine = Machine::from_words(program, VecDeque::new(), ());
+        let fault = machine.run().unwrap_err();
+
+        assert_eq!(4, fault.pc);
+        assert_eq!(
+            vec![
+                (0, "DATA 1101".to_string()),
+                (1, "DATA 1".to_string()),
+                (2, format!("DATA {}", huge)),
+                (3, "DATA 5".to_string()),
+                (4, "DATA 42".to_string()),
+                (5, format!("DATA {}", huge + 1)),
+            ],
+            fault.window()
+        );
+    }
+}
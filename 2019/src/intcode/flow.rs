@@ -0,0 +1,404 @@
+//! Control-flow graph recovery for intcode programs.
+//!
+//! [`Graph::recover`] decodes the instructions reachable from address 0 (see [`Item::decode`]),
+//! following fall-through and jumps with immediate targets, and splits them into basic blocks.
+//! Jumps whose target is only known at runtime, such as returns through the relative base, are
+//! flagged as dynamic. Since their targets are unknown, the address after an unconditional jump
+//! is also explored if the program stores it as a constant somewhere, which is how programs
+//! commonly pass return addresses. Stores which write over a reachable instruction are flagged
+//! as self-modifying.
+//!
+//! The graph can be rendered with Graphviz through [`Graph::to_dot`].
+
+use super::disasm::{Item, Operand};
+use super::{Mode, Opcode};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+
+/// How control reaches the successor of a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// Execution continues with the next instruction.
+    Fallthrough,
+    /// A jump is taken.
+    Jump,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    pub target: usize,
+    pub kind: EdgeKind,
+}
+
+/// A store which writes over a reachable instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelfModification {
+    /// The address of the storing instruction.
+    pub store: usize,
+    /// The address written.
+    pub addr: usize,
+    /// The address of the instruction containing `addr`.
+    pub instruction: usize,
+}
+
+/// A sequence of instructions which is only entered at its first one and only left after its
+/// last one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub start: usize,
+    /// One past the last word of the block.
+    pub end: usize,
+    pub instructions: Vec<(usize, Item)>,
+    pub successors: Vec<Edge>,
+    /// Whether the block ends with a jump whose target is only known at runtime.
+    pub dynamic_jump: bool,
+}
+
+/// The control-flow graph of a program, see the [module documentation](self).
+#[derive(Debug, Clone)]
+pub struct Graph {
+    blocks: BTreeMap<usize, Block>,
+    self_modifications: Vec<SelfModification>,
+}
+
+/// Where control may go after an instruction, and whether that includes a dynamic target.
+fn successors(addr: usize, item: &Item) -> (Vec<Edge>, bool) {
+    let next = addr + item.size();
+    let fallthrough = Edge {
+        target: next,
+        kind: EdgeKind::Fallthrough,
+    };
+    let (opcode, operands) = match item {
+        Item::Instruction { opcode, operands } => (*opcode, operands),
+        // Executing data faults or does something the disassembler does not understand
+        Item::Data(_) => return (Vec::new(), false),
+    };
+
+    match opcode {
+        Opcode::Halt => (Vec::new(), false),
+        Opcode::JumpNotZero | Opcode::JumpZero => {
+            // Whether the jump is taken, if the condition is known
+            let taken = match operands[0] {
+                Operand {
+                    mode: Mode::Immidiate,
+                    value,
+                } => Some((value != 0) == (opcode == Opcode::JumpNotZero)),
+                _ => None,
+            };
+
+            let mut edges = Vec::new();
+            if taken != Some(true) {
+                edges.push(fallthrough);
+            }
+            let mut dynamic = false;
+            if taken != Some(false) {
+                match item.jump_target() {
+                    Some(target) => edges.push(Edge {
+                        target,
+                        kind: EdgeKind::Jump,
+                    }),
+                    None => dynamic = true,
+                }
+            }
+            (edges, dynamic)
+        }
+        _ => (vec![fallthrough], false),
+    }
+}
+
+/// The operand an instruction writes to, if any.
+fn written(item: &Item) -> Option<(usize, Operand)> {
+    match item {
+        Item::Instruction { opcode, operands } => match opcode {
+            Opcode::Add | Opcode::Mul | Opcode::LessThan | Opcode::Equal => Some((2, operands[2])),
+            Opcode::Input => Some((0, operands[0])),
+            _ => None,
+        },
+        Item::Data(_) => None,
+    }
+}
+
+/// The constant an instruction stores, if it computes one from immediates only.
+fn stored_constant(item: &Item) -> Option<isize> {
+    match item {
+        Item::Instruction { opcode, operands } => {
+            let (lhs, rhs) = match operands.as_slice() {
+                [Operand {
+                    mode: Mode::Immidiate,
+                    value: lhs,
+                }, Operand {
+                    mode: Mode::Immidiate,
+                    value: rhs,
+                }, _] => (*lhs, *rhs),
+                _ => return None,
+            };
+            match opcode {
+                Opcode::Add => lhs.checked_add(rhs),
+                Opcode::Mul => lhs.checked_mul(rhs),
+                _ => None,
+            }
+        }
+        Item::Data(_) => None,
+    }
+}
+
+impl Graph {
+    /// Recovers the control-flow graph of a program.
+    pub fn recover(program: &[isize]) -> Self {
+        let mut instructions = BTreeMap::new();
+        let mut leaders = BTreeSet::new();
+        let mut roots = vec![0];
+        while !roots.is_empty() {
+            leaders.extend(roots.iter().copied());
+            explore(program, roots, &mut instructions, &mut leaders);
+
+            // The addresses after unconditional jumps which the program stores as constants
+            let constants: BTreeSet<_> =
+                instructions.values().filter_map(stored_constant).collect();
+            roots = instructions
+                .iter()
+                .filter(|(&addr, item)| {
+                    let (edges, _) = successors(addr, item);
+                    matches!(
+                        item,
+                        Item::Instruction {
+                            opcode: Opcode::JumpNotZero | Opcode::JumpZero,
+                            ..
+                        }
+                    ) && !edges.iter().any(|edge| edge.kind == EdgeKind::Fallthrough)
+                })
+                .map(|(&addr, item)| addr + item.size())
+                .filter(|&next| {
+                    !instructions.contains_key(&next)
+                        && next < program.len()
+                        && constants.contains(&(next as isize))
+                })
+                .collect();
+        }
+
+        let mut blocks = BTreeMap::new();
+        let mut current: Option<Block> = None;
+        for (&addr, item) in &instructions {
+            if let Some(block) = current.take() {
+                let falls_through = block.successors.iter().any(|edge| edge.target == addr);
+                if leaders.contains(&addr) || block.end != addr || !falls_through {
+                    blocks.insert(block.start, block);
+                } else {
+                    current = Some(block);
+                }
+            }
+
+            let (edges, dynamic_jump) = successors(addr, item);
+            let block = current.get_or_insert_with(|| Block {
+                start: addr,
+                end: addr,
+                instructions: Vec::new(),
+                successors: Vec::new(),
+                dynamic_jump: false,
+            });
+            block.end = addr + item.size();
+            block.instructions.push((addr, item.clone()));
+            block.successors = edges;
+            block.dynamic_jump = dynamic_jump;
+        }
+        if let Some(block) = current {
+            blocks.insert(block.start, block);
+        }
+        // Falling through past the end of the program leads nowhere known
+        let starts: BTreeSet<_> = blocks.keys().copied().collect();
+        for block in blocks.values_mut() {
+            block
+                .successors
+                .retain(|edge| starts.contains(&edge.target));
+        }
+
+        let self_modifications = instructions
+            .iter()
+            .filter_map(|(&store, item)| {
+                let (i, operand) = written(item)?;
+                let addr = match operand.mode {
+                    Mode::Position if operand.value >= 0 => operand.value as usize,
+                    Mode::Immidiate => store + 1 + i,
+                    _ => return None,
+                };
+                let (&instruction, target) = instructions.range(..=addr).next_back()?;
+                if addr < instruction + target.size() {
+                    Some(SelfModification {
+                        store,
+                        addr,
+                        instruction,
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        Graph {
+            blocks,
+            self_modifications,
+        }
+    }
+
+    /// The blocks by their start address.
+    pub fn blocks(&self) -> &BTreeMap<usize, Block> {
+        &self.blocks
+    }
+
+    /// The block starting at `addr`, if any.
+    pub fn block(&self, addr: usize) -> Option<&Block> {
+        self.blocks.get(&addr)
+    }
+
+    /// The stores which write over reachable instructions.
+    pub fn self_modifications(&self) -> &[SelfModification] {
+        &self.self_modifications
+    }
+
+    /// The addresses of the blocks ending with a dynamic jump.
+    pub fn dynamic_jumps(&self) -> impl Iterator<Item = usize> + '_ {
+        self.blocks
+            .values()
+            .filter(|block| block.dynamic_jump)
+            .map(|block| block.start)
+    }
+
+    /// Renders the graph in the Graphviz DOT language.
+    ///
+    /// Blocks ending with a dynamic jump link to a single node standing for every dynamic target,
+    /// and blocks containing self-modifying stores are highlighted.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        dot.push_str("digraph intcode {\n");
+        dot.push_str("    node [shape=box, fontname=monospace];\n");
+
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            for (addr, item) in &block.instructions {
+                write!(label, "{}: {}", addr, item).unwrap();
+                for modification in &self.self_modifications {
+                    if modification.store == *addr {
+                        write!(label, " ; modifies {}", modification.instruction).unwrap();
+                    }
+                }
+                label.push_str("\\l");
+            }
+            let modifies = self
+                .self_modifications
+                .iter()
+                .any(|modification| (block.start..block.end).contains(&modification.store));
+
+            write!(dot, "    b{} [label=\"{}\"", block.start, label).unwrap();
+            if modifies {
+                dot.push_str(", color=orange");
+            }
+            dot.push_str("];\n");
+
+            for edge in &block.successors {
+                write!(dot, "    b{} -> b{}", block.start, edge.target).unwrap();
+                if edge.kind == EdgeKind::Fallthrough {
+                    dot.push_str(" [style=dashed]");
+                }
+                dot.push_str(";\n");
+            }
+            if block.dynamic_jump {
+                writeln!(dot, "    b{} -> dynamic [style=dotted];", block.start).unwrap();
+            }
+        }
+
+        if self.dynamic_jumps().next().is_some() {
+            dot.push_str("    dynamic [shape=diamond, label=\"?\"];\n");
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// Decodes every instruction reachable from `roots` which was not decoded yet, adding jump
+/// targets and the instructions after jumps to `leaders`.
+fn explore(
+    program: &[isize],
+    mut roots: Vec<usize>,
+    instructions: &mut BTreeMap<usize, Item>,
+    leaders: &mut BTreeSet<usize>,
+) {
+    while let Some(addr) = roots.pop() {
+        if addr >= program.len() || instructions.contains_key(&addr) {
+            continue;
+        }
+        let item = Item::decode(program, addr);
+        let (edges, dynamic) = successors(addr, &item);
+        let jumps = dynamic || edges.iter().any(|edge| edge.kind == EdgeKind::Jump);
+        for edge in edges {
+            if jumps {
+                leaders.insert(edge.target);
+            }
+            roots.push(edge.target);
+        }
+        instructions.insert(addr, item);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_at_jumps_and_targets() {
+        // Counts 20 up to 3, then outputs it
+        let program = vec![1001, 20, 1, 20, 1007, 20, 3, 21, 1005, 21, 0, 4, 20, 99];
+        let graph = Graph::recover(&program);
+
+        let blocks: Vec<_> = graph.blocks().values().map(|b| (b.start, b.end)).collect();
+        assert_eq!(vec![(0, 11), (11, 14)], blocks);
+        assert_eq!(
+            vec![
+                Edge {
+                    target: 11,
+                    kind: EdgeKind::Fallthrough
+                },
+                Edge {
+                    target: 0,
+                    kind: EdgeKind::Jump
+                }
+            ],
+            graph.block(0).unwrap().successors
+        );
+        assert!(graph.block(11).unwrap().successors.is_empty());
+        assert!(graph.self_modifications().is_empty());
+    }
+
+    #[test]
+    fn flags_dynamic_jumps_and_self_modification() {
+        // Stores the return address 7 and calls 8, which writes over the halt at 7 and returns
+        // through the relative base
+        let program = vec![21101, 7, 0, 0, 1105, 1, 8, 99, 1101, 0, 99, 7, 2105, 1, 0];
+        let graph = Graph::recover(&program);
+
+        assert_eq!(
+            vec![0, 7, 8],
+            graph.blocks().keys().copied().collect::<Vec<_>>()
+        );
+        assert_eq!(vec![8], graph.dynamic_jumps().collect::<Vec<_>>());
+        assert_eq!(
+            &[SelfModification {
+                store: 8,
+                addr: 7,
+                instruction: 7
+            }],
+            graph.self_modifications()
+        );
+    }
+
+    #[test]
+    fn exports_dot() {
+        let dot = Graph::recover(&[1105, 1, 4, 42, 99, 1106, 0, 0]).to_dot();
+
+        assert_eq!(
+            "digraph intcode {\n    node [shape=box, fontname=monospace];\n    \
+             b0 [label=\"0: JNZ  #1, #4\\l\"];\n    b0 -> b4;\n    \
+             b4 [label=\"4: HALT\\l\"];\n}\n",
+            dot
+        );
+    }
+}
@@ -0,0 +1,157 @@
+//! Undo logs for stepping machines backwards, see
+//! [`Machine::start_history`](super::Machine::start_history).
+//!
+//! A [`History`] keeps an [`Entry`] for each executed instruction with everything needed to undo
+//! it: where it executed, the relative base before it, the values it overwrote and the I/O it
+//! did. Only the most recent entries are kept, hence a history can stay enabled for long runs.
+
+use std::collections::VecDeque;
+
+/// How to undo an executed instruction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry<W = isize> {
+    /// The number of instructions executed before this one.
+    pub step: usize,
+    pub pc: usize,
+    /// The relative base before the instruction executed.
+    pub relative_base: isize,
+    /// The addresses written by the instruction together with the values they held before, in
+    /// the order they were written.
+    pub writes: Vec<(usize, W)>,
+    /// The value read from the input.
+    pub input: Option<W>,
+    /// The value written to the output.
+    pub output: Option<W>,
+}
+
+/// The most recently executed instructions of a machine.
+#[derive(Debug, Clone)]
+pub struct History<W = isize> {
+    entries: VecDeque<Entry<W>>,
+    capacity: usize,
+}
+
+impl<W> History<W> {
+    /// Creates a history which keeps the last `capacity` instructions.
+    pub fn new(capacity: usize) -> Self {
+        History {
+            entries: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The kept entries, oldest first.
+    pub fn entries(&self) -> impl DoubleEndedIterator<Item = &Entry<W>> {
+        self.entries.iter()
+    }
+
+    /// The most recent instruction which wrote `addr`, if it is still kept.
+    pub fn last_write(&self, addr: usize) -> Option<&Entry<W>> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|entry| entry.writes.iter().any(|&(written, _)| written == addr))
+    }
+
+    pub(super) fn push(&mut self, entry: Entry<W>) {
+        if self.entries.len() == self.capacity {
+            if self.capacity == 0 {
+                return;
+            }
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    pub(super) fn pop(&mut self) -> Option<Entry<W>> {
+        self.entries.pop_back()
+    }
+
+    /// Forgets every entry, e.g. because the state they undo was replaced.
+    pub(super) fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Machine;
+    use super::*;
+
+    /// Adds up input values until it reads zero, outputting each partial sum
+    const SUMS: &[isize] = &[
+        3, 15, 1006, 15, 14, 1, 15, 16, 16, 4, 16, 1105, 1, 0, 99, 0, 0,
+    ];
+
+    fn machine(input: Vec<isize>, capacity: usize) -> Machine<VecDeque<isize>, Vec<isize>> {
+        let mut machine = Machine::new(SUMS.to_vec(), input.into(), Vec::new());
+        machine.start_history(History::new(capacity));
+        machine
+    }
+
+    #[test]
+    fn steps_back_to_start() {
+        let mut machine = machine(vec![3, 4, 0], 100);
+        let initial = machine.snapshot();
+        machine.run_until_event();
+        machine.run_until_event();
+        assert_eq!(&[3, 7], &machine.output()[..]);
+
+        let mut inputs = Vec::new();
+        let mut outputs = Vec::new();
+        while let Some(entry) = machine.step_back() {
+            inputs.extend(entry.input);
+            outputs.extend(entry.output);
+        }
+        assert_eq!(vec![4, 3], inputs);
+        assert_eq!(vec![7, 3], outputs);
+        assert_eq!(initial, machine.snapshot());
+        assert!(machine.history().unwrap().is_empty());
+    }
+
+    #[test]
+    fn finds_last_write() {
+        let mut machine = machine(vec![3, 4, 0], 100);
+        machine.run_until_event();
+        machine.run_until_event();
+
+        // The sum at 16 was last written by the addition of the second round
+        let entry = machine.history().unwrap().last_write(16).unwrap();
+        assert_eq!(
+            (7, 5, vec![(16, 3)]),
+            (entry.step, entry.pc, entry.writes.clone())
+        );
+
+        assert_eq!(2, machine.run_back_to(5).len());
+        assert_eq!((5, 7), (machine.pc(), machine.steps()));
+        assert_eq!(3, machine.peek(16));
+        assert_eq!(2, machine.history().unwrap().last_write(16).unwrap().step);
+    }
+
+    #[test]
+    fn keeps_only_recent_entries() {
+        let mut machine = machine(vec![1; 50], 10);
+        machine.run_until_event();
+        while let crate::intcode::State::Output(_) = machine.run_until_event() {}
+        let steps = machine.steps();
+
+        let history = machine.history().unwrap();
+        assert_eq!(10, history.len());
+        assert_eq!(steps - 10, history.entries().next().unwrap().step);
+        assert_eq!(10, machine.run_back_to(usize::MAX).len());
+        assert_eq!(steps - 10, machine.steps());
+        assert!(machine.step_back().is_none());
+    }
+}
@@ -0,0 +1,179 @@
+//! Input and output backends for a [`Machine`](super::Machine).
+//!
+//! A machine reads values from an [`Input`] when executing `Opcode::Input` and writes values to
+//! an [`Output`] when executing `Opcode::Output`. Buffers, iterators, closures and channels can
+//! all be plugged in, as can mutable references to any of them.
+
+use super::{Error, Result};
+use std::collections::VecDeque;
+use std::sync::mpsc::{Receiver, Sender, SyncSender, TryRecvError, TrySendError};
+use std::task::Poll;
+
+/// A source of values for a machine.
+pub trait Input<W = isize> {
+    /// Reads the next value.
+    ///
+    /// Returns `Poll::Pending` if no value is available yet and fails with `Error::InputClosed`
+    /// if no value will ever become available.
+    fn read(&mut self) -> Result<Poll<W>>;
+}
+
+/// A destination for values produced by a machine.
+pub trait Output<W = isize> {
+    /// Writes a value.
+    ///
+    /// Returns `Poll::Pending` if the value cannot be accepted yet and fails with
+    /// `Error::OutputClosed` if no value will ever be accepted again.
+    fn write(&mut self, value: W) -> Result<Poll<()>>;
+}
+
+impl<W, T: Input<W> + ?Sized> Input<W> for &mut T {
+    fn read(&mut self) -> Result<Poll<W>> {
+        (**self).read()
+    }
+}
+
+impl<W, T: Output<W> + ?Sized> Output<W> for &mut T {
+    fn write(&mut self, value: W) -> Result<Poll<()>> {
+        (**self).write(value)
+    }
+}
+
+/// An input which is always closed.
+impl<W> Input<W> for () {
+    fn read(&mut self) -> Result<Poll<W>> {
+        Err(Error::InputClosed)
+    }
+}
+
+/// An output which discards every value.
+impl<W> Output<W> for () {
+    fn write(&mut self, _: W) -> Result<Poll<()>> {
+        Ok(Poll::Ready(()))
+    }
+}
+
+/// A buffer which can be refilled, hence an empty buffer blocks instead of being closed.
+impl<W> Input<W> for VecDeque<W> {
+    fn read(&mut self) -> Result<Poll<W>> {
+        Ok(self.pop_front().map_or(Poll::Pending, Poll::Ready))
+    }
+}
+
+impl<W> Output<W> for VecDeque<W> {
+    fn write(&mut self, value: W) -> Result<Poll<()>> {
+        self.push_back(value);
+        Ok(Poll::Ready(()))
+    }
+}
+
+impl<W> Output<W> for Vec<W> {
+    fn write(&mut self, value: W) -> Result<Poll<()>> {
+        self.push(value);
+        Ok(Poll::Ready(()))
+    }
+}
+
+impl<W> Input<W> for Receiver<W> {
+    fn read(&mut self) -> Result<Poll<W>> {
+        match self.try_recv() {
+            Ok(value) => Ok(Poll::Ready(value)),
+            Err(TryRecvError::Empty) => Ok(Poll::Pending),
+            Err(TryRecvError::Disconnected) => Err(Error::InputClosed),
+        }
+    }
+}
+
+impl<W> Output<W> for SyncSender<W> {
+    fn write(&mut self, value: W) -> Result<Poll<()>> {
+        match self.try_send(value) {
+            Ok(()) => Ok(Poll::Ready(())),
+            Err(TrySendError::Full(_)) => Ok(Poll::Pending),
+            Err(TrySendError::Disconnected(_)) => Err(Error::OutputClosed),
+        }
+    }
+}
+
+impl<W> Output<W> for Sender<W> {
+    fn write(&mut self, value: W) -> Result<Poll<()>> {
+        self.send(value).map_err(|_| Error::OutputClosed)?;
+        Ok(Poll::Ready(()))
+    }
+}
+
+/// An input which reads from an iterator, see [`from_iter`].
+#[derive(Debug, Clone)]
+pub struct Iter<I>(I);
+
+/// Creates an input which reads values from an iterator and is closed once it is exhausted.
+pub fn from_iter<I: IntoIterator>(iter: I) -> Iter<I::IntoIter> {
+    Iter(iter.into_iter())
+}
+
+impl<I: Iterator> Input<I::Item> for Iter<I> {
+    fn read(&mut self) -> Result<Poll<I::Item>> {
+        self.0.next().map(Poll::Ready).ok_or(Error::InputClosed)
+    }
+}
+
+/// An output which passes every value to a closure, see [`from_fn`].
+#[derive(Debug, Clone)]
+pub struct FromFn<F>(F);
+
+/// Creates an output which calls `f` with every value written.
+pub fn from_fn<W, F>(f: F) -> FromFn<F>
+where
+    F: FnMut(W),
+{
+    FromFn(f)
+}
+
+impl<W, F: FnMut(W)> Output<W> for FromFn<F> {
+    fn write(&mut self, value: W) -> Result<Poll<()>> {
+        (self.0)(value);
+        Ok(Poll::Ready(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::sync_channel;
+
+    #[test]
+    fn buffer_blocks_when_empty() {
+        let mut buffer: VecDeque<isize> = vec![1].into();
+
+        assert_eq!(Poll::Ready(1), buffer.read().unwrap());
+        assert_eq!(Poll::Pending, buffer.read().unwrap());
+    }
+
+    #[test]
+    fn iterator_closes_when_exhausted() {
+        let mut input = from_iter(vec![1, 2]);
+
+        assert_eq!(Poll::Ready(1), input.read().unwrap());
+        assert_eq!(Poll::Ready(2), input.read().unwrap());
+        assert!(matches!(input.read(), Err(Error::InputClosed)));
+    }
+
+    #[test]
+    fn channel_pair() {
+        let (mut tx, mut rx) = sync_channel::<isize>(1);
+
+        assert_eq!(Poll::Ready(()), tx.write(1).unwrap());
+        assert_eq!(Poll::Pending, tx.write(2).unwrap());
+        assert_eq!(Poll::Ready(1), rx.read().unwrap());
+
+        drop(tx);
+        assert!(matches!(rx.read(), Err(Error::InputClosed)));
+    }
+
+    #[test]
+    fn closure_output() {
+        let mut sum = 0;
+        assert_eq!(Poll::Ready(()), from_fn(|v| sum += v).write(3).unwrap());
+
+        assert_eq!(3, sum);
+    }
+}
@@ -0,0 +1,226 @@
+//! Sparse memory for intcode machines.
+//!
+//! Memory is split into fixed size pages which are only allocated once a non-zero value is
+//! written to them, so programs can use huge addresses without allocating everything below them.
+//! Pages are shared between clones until one of them writes to it, which makes cloning a machine
+//! or taking a snapshot cheap.
+
+use super::{MemoryLimit, Result, Word};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// The number of words in a page.
+pub const PAGE_SIZE: usize = 1024;
+
+/// The default number of words a machine may allocate, see [`Memory::set_limit`].
+pub const DEFAULT_LIMIT: usize = 1 << 24;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "RawMemory<W>")]
+pub struct Memory<W = isize> {
+    pages: BTreeMap<usize, Arc<Vec<W>>>,
+    limit: usize,
+}
+
+/// A memory whose pages have not been checked yet.
+#[derive(Deserialize)]
+struct RawMemory<W> {
+    pages: BTreeMap<usize, Arc<Vec<W>>>,
+    limit: usize,
+}
+
+impl<W> TryFrom<RawMemory<W>> for Memory<W> {
+    type Error = String;
+
+    fn try_from(raw: RawMemory<W>) -> Result<Self, Self::Error> {
+        if let Some((index, page)) = raw.pages.iter().find(|(_, page)| page.len() != PAGE_SIZE) {
+            return Err(format!("page {} has {} words", index, page.len()));
+        }
+        // Addresses are never larger than an isize, which keeps page arithmetic from overflowing
+        match raw.pages.keys().next_back() {
+            Some(&index) if index > isize::MAX as usize / PAGE_SIZE => {
+                Err(format!("page {} is out of range", index))
+            }
+            _ => Ok(Memory {
+                pages: raw.pages,
+                limit: raw.limit,
+            }),
+        }
+    }
+}
+
+impl<W> Default for Memory<W> {
+    fn default() -> Self {
+        Memory {
+            pages: BTreeMap::new(),
+            limit: DEFAULT_LIMIT,
+        }
+    }
+}
+
+impl<W: Word> From<Vec<W>> for Memory<W> {
+    /// Loads a program at address 0. The program is always loaded, even if it exceeds the limit.
+    fn from(program: Vec<W>) -> Self {
+        let pages = program
+            .chunks(PAGE_SIZE)
+            .enumerate()
+            .map(|(index, chunk)| {
+                let mut page = chunk.to_vec();
+                page.resize(PAGE_SIZE, W::zero());
+                (index, Arc::new(page))
+            })
+            .collect();
+        Memory {
+            pages,
+            ..Memory::default()
+        }
+    }
+}
+
+impl<W: Word> Memory<W> {
+    /// Reads an address, every address which was never written holds zero.
+    pub fn get(&self, addr: usize) -> W {
+        self.pages
+            .get(&(addr / PAGE_SIZE))
+            .map_or_else(W::zero, |page| page[addr % PAGE_SIZE].clone())
+    }
+
+    /// Writes an address, failing if that requires allocating a page beyond the limit.
+    pub fn set(&mut self, addr: usize, value: W) -> Result<()> {
+        let index = addr / PAGE_SIZE;
+        if !self.pages.contains_key(&index) {
+            if value.is_zero() {
+                return Ok(());
+            }
+            self.check(addr)?;
+        }
+
+        let page = self
+            .pages
+            .entry(index)
+            .or_insert_with(|| Arc::new(vec![W::zero(); PAGE_SIZE]));
+        Arc::make_mut(page)[addr % PAGE_SIZE] = value;
+        Ok(())
+    }
+
+    /// Fails like [`Memory::set`] would when writing a non-zero value to `addr`, without writing
+    /// anything.
+    pub fn check(&self, addr: usize) -> Result<()> {
+        snafu::ensure!(
+            self.pages.contains_key(&(addr / PAGE_SIZE))
+                || self.allocated() + PAGE_SIZE <= self.limit,
+            MemoryLimit {
+                addr,
+                limit: self.limit
+            }
+        );
+        Ok(())
+    }
+
+    /// The number of words allocated so far.
+    pub fn allocated(&self) -> usize {
+        self.pages.len() * PAGE_SIZE
+    }
+
+    /// The maximum number of words which may be allocated.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Sets the maximum number of words which may be allocated, which does not free any pages
+    /// already allocated.
+    pub fn set_limit(&mut self, words: usize) {
+        self.limit = words;
+    }
+
+    /// One past the highest address which may hold a non-zero value.
+    pub fn len(&self) -> usize {
+        self.pages
+            .keys()
+            .next_back()
+            .map_or(0, |index| (index + 1) * PAGE_SIZE)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pages.is_empty()
+    }
+
+    /// The addresses of the allocated page containing `addr`, or else of the first allocated page
+    /// after it, which skips over addresses that can only hold zero.
+    pub fn next_allocated(&self, addr: usize) -> Option<Range<usize>> {
+        let (&index, _) = self.pages.range(addr / PAGE_SIZE..).next()?;
+        Some(index * PAGE_SIZE..(index + 1) * PAGE_SIZE)
+    }
+
+    /// Copies the words from `start` up to `end`.
+    pub fn read_range(&self, start: usize, end: usize) -> Vec<W> {
+        (start..end).map(|addr| self.get(addr)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::Error;
+
+    #[test]
+    fn allocates_pages_on_write() {
+        let mut memory: Memory = Memory::from(vec![1, 2, 3]);
+        assert_eq!(PAGE_SIZE, memory.allocated());
+
+        memory.set(1 << 40, 0).unwrap();
+        assert_eq!(PAGE_SIZE, memory.allocated());
+        memory.set(1 << 40, 7).unwrap();
+        assert_eq!(2 * PAGE_SIZE, memory.allocated());
+        assert_eq!(7, memory.get(1 << 40));
+        assert_eq!(0, memory.get((1 << 40) + 1));
+        assert_eq!(3, memory.get(2));
+    }
+
+    #[test]
+    fn finds_allocated_pages() {
+        let mut memory: Memory = Memory::from(vec![1, 2, 3]);
+        memory.set(1 << 40, 7).unwrap();
+
+        assert_eq!(Some(0..PAGE_SIZE), memory.next_allocated(5));
+        let far = 1 << 40;
+        assert_eq!(Some(far..far + PAGE_SIZE), memory.next_allocated(PAGE_SIZE));
+        assert_eq!(None, memory.next_allocated(far + PAGE_SIZE));
+    }
+
+    #[test]
+    fn enforces_limit() {
+        let mut memory: Memory = Memory::from(vec![1, 2, 3]);
+        memory.set_limit(PAGE_SIZE);
+
+        memory.set(PAGE_SIZE - 1, 4).unwrap();
+        assert!(memory.check(PAGE_SIZE - 1).is_ok());
+        assert!(memory.check(PAGE_SIZE).is_err());
+        assert!(matches!(
+            memory.set(PAGE_SIZE, 5),
+            Err(Error::MemoryLimit { addr, limit: PAGE_SIZE }) if addr == PAGE_SIZE
+        ));
+    }
+
+    #[test]
+    fn clones_share_pages_until_written() {
+        let memory: Memory = Memory::from(vec![1, 2, 3]);
+        let mut clone = memory.clone();
+        assert!(Arc::ptr_eq(&memory.pages[&0], &clone.pages[&0]));
+
+        clone.set(0, 5).unwrap();
+        assert_eq!(1, memory.get(0));
+        assert_eq!(5, clone.get(0));
+    }
+
+    #[test]
+    fn rejects_pages_out_of_range() {
+        let mut memory = serde_json::to_value(Memory::from(vec![1isize])).unwrap();
+        memory["pages"][usize::MAX.to_string()] = memory["pages"]["0"].clone();
+
+        assert!(serde_json::from_value::<Memory>(memory).is_err());
+    }
+}
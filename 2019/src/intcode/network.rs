@@ -0,0 +1,234 @@
+//! Networks of intcode machines.
+//!
+//! A [`Network`] owns a set of machines and directed links between them. Every value a machine
+//! outputs is sent to the input of each machine it links to, and values of machines without
+//! outgoing links are kept in their output. Pipelines, feedback rings and arbitrary graphs are
+//! all built from [`Network::add`] and [`Network::connect`].
+//!
+//! Machines are scheduled cooperatively: each one runs until it needs input or halts, then its
+//! output is delivered and the next one runs.
+
+use super::{Machine, State, Word};
+use snafu::Snafu;
+use std::collections::VecDeque;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("deadlock, machines {:?} are waiting for input", waiting))]
+    Deadlock { waiting: Vec<usize> },
+    #[snafu(display("machine {} faulted: {}", node, source))]
+    Fault { node: usize, source: super::Fault },
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A machine of a network, which reads from and writes to buffers.
+pub type Node<W = isize> = Machine<VecDeque<W>, VecDeque<W>, W>;
+
+#[derive(Debug, Clone)]
+struct Entry<W> {
+    machine: Node<W>,
+    targets: Vec<usize>,
+    halted: bool,
+    last_output: Option<W>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Network<W = isize> {
+    entries: Vec<Entry<W>>,
+}
+
+impl<W> Default for Network<W> {
+    fn default() -> Self {
+        Network {
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl<W: Word> Network<W> {
+    pub fn new() -> Self {
+        Network::default()
+    }
+
+    /// Creates a network where each machine sends its output to the next one.
+    pub fn pipeline<T: IntoIterator<Item = Node<W>>>(machines: T) -> Self {
+        let mut network = Network::new();
+        for machine in machines {
+            let id = network.add(machine);
+            if id > 0 {
+                network.connect(id - 1, id);
+            }
+        }
+        network
+    }
+
+    /// Creates a pipeline where the last machine sends its output back to the first one.
+    pub fn ring<T: IntoIterator<Item = Node<W>>>(machines: T) -> Self {
+        let mut network = Network::pipeline(machines);
+        if let Some(last) = network.len().checked_sub(1) {
+            network.connect(last, 0);
+        }
+        network
+    }
+
+    /// Adds a machine, returning its id.
+    pub fn add(&mut self, machine: Node<W>) -> usize {
+        self.entries.push(Entry {
+            machine,
+            targets: Vec::new(),
+            halted: false,
+            last_output: None,
+        });
+        self.entries.len() - 1
+    }
+
+    /// Sends the output of machine `from` to machine `to`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either machine does not exist.
+    pub fn connect(&mut self, from: usize, to: usize) {
+        assert!(to < self.entries.len(), "no machine {}", to);
+        self.entries[from].targets.push(to);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn machine(&self, id: usize) -> &Node<W> {
+        &self.entries[id].machine
+    }
+
+    pub fn machine_mut(&mut self, id: usize) -> &mut Node<W> {
+        &mut self.entries[id].machine
+    }
+
+    /// Queues a value on the input of a machine.
+    pub fn send(&mut self, id: usize, value: W) {
+        self.entries[id].machine.input_mut().push_back(value);
+    }
+
+    /// The last value a machine output, whether it was delivered to other machines or not.
+    pub fn last_output(&self, id: usize) -> Option<&W> {
+        self.entries[id].last_output.as_ref()
+    }
+
+    /// Runs every machine until all of them halted.
+    ///
+    /// Fails when a machine faults, or when no machine can make progress because each one which
+    /// did not halt is waiting for input nobody is going to send.
+    pub fn run(&mut self) -> Result<()> {
+        loop {
+            let mut progress = false;
+            for id in 0..self.entries.len() {
+                progress |= self.run_machine(id)?;
+            }
+
+            let waiting: Vec<_> = (0..self.entries.len())
+                .filter(|&id| !self.entries[id].halted)
+                .collect();
+            if waiting.is_empty() {
+                break Ok(());
+            }
+            if !progress {
+                break Deadlock { waiting }.fail();
+            }
+        }
+    }
+
+    /// Runs a machine until it needs input or halts and delivers its output, returning whether
+    /// it executed any instruction.
+    fn run_machine(&mut self, id: usize) -> Result<bool> {
+        let entry = &mut self.entries[id];
+        if entry.halted {
+            return Ok(false);
+        }
+
+        let steps = entry.machine.steps();
+        loop {
+            match entry.machine.run_until_event() {
+                State::Running | State::Output(_) => {}
+                State::NeedsInput | State::OutputBlocked => break,
+                State::Halted(_) => {
+                    entry.halted = true;
+                    break;
+                }
+                State::Faulted(source) => return Err(Error::Fault { node: id, source }),
+            }
+        }
+        let progress = entry.machine.steps() != steps;
+
+        if let Some(value) = entry.machine.output().back() {
+            entry.last_output = Some(value.clone());
+        }
+        if !entry.targets.is_empty() {
+            let targets = entry.targets.clone();
+            let values: Vec<_> = entry.machine.output_mut().drain(..).collect();
+            for target in targets {
+                let input = self.entries[target].machine.input_mut();
+                input.extend(values.iter().cloned());
+            }
+        }
+        Ok(progress)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Adds `n` to every value until it reads zero, which it passes on before halting
+    fn adder(n: isize) -> Node {
+        let program = vec![
+            3, 17, 1006, 17, 14, 1001, 17, n, 18, 4, 18, 1105, 1, 0, 104, 0, 99, 0, 0,
+        ];
+        Machine::new(program, VecDeque::new(), VecDeque::new())
+    }
+
+    #[test]
+    fn pipeline_passes_values_along() {
+        let mut network = Network::pipeline(vec![adder(1), adder(10), adder(100)]);
+        network.send(0, 5);
+        network.send(0, 0);
+
+        network.run().unwrap();
+        let output: Vec<_> = network.machine(2).output().iter().copied().collect();
+        assert_eq!(vec![116, 0], output);
+    }
+
+    #[test]
+    fn feedback_ring() {
+        let program: Vec<isize> = vec![
+            3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1,
+            28, 1005, 28, 6, 99, 0, 0, 5,
+        ];
+        let machines = vec![9, 8, 7, 6, 5].into_iter().map(|phase| {
+            Machine::new(
+                program.clone(),
+                VecDeque::from(vec![phase]),
+                VecDeque::new(),
+            )
+        });
+
+        let mut network = Network::ring(machines);
+        network.send(0, 0);
+        network.run().unwrap();
+        assert_eq!(Some(&139_629_729), network.last_output(4));
+    }
+
+    #[test]
+    fn detects_deadlock() {
+        let mut network = Network::ring(vec![adder(1), adder(2)]);
+
+        assert!(matches!(
+            network.run(),
+            Err(Error::Deadlock { ref waiting }) if waiting == &[0, 1]
+        ));
+    }
+}
@@ -0,0 +1,139 @@
+//! Parsing intcode programs.
+//!
+//! Programs are written as comma separated words, usually on a single line. A [`Program`] parses
+//! them with `str::parse`, ignoring whitespace around words and lines, and reports which word is
+//! invalid instead of panicking.
+
+use super::Machine;
+use snafu::{ResultExt as _, Snafu};
+use std::collections::VecDeque;
+use std::fmt;
+use std::num::ParseIntError;
+use std::ops::{Deref, DerefMut};
+use std::str::FromStr;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("invalid word {} '{}': {}", index, text, source))]
+    InvalidWord {
+        index: usize,
+        text: String,
+        source: ParseIntError,
+    },
+}
+
+/// The initial memory of a machine.
+///
+/// A program dereferences to its words, hence it can be patched before a machine is created.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Program {
+    words: Vec<isize>,
+}
+
+impl Program {
+    /// Creates a machine which starts with the program in memory.
+    pub fn machine<I, O>(&self, input: I, output: O) -> Machine<I, O> {
+        self.clone().into_machine(input, output)
+    }
+
+    pub fn into_machine<I, O>(self, input: I, output: O) -> Machine<I, O> {
+        Machine::new(self.words, input, output)
+    }
+
+    pub fn into_words(self) -> Vec<isize> {
+        self.words
+    }
+}
+
+impl FromStr for Program {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words = s
+            .trim()
+            .split(',')
+            .map(str::trim)
+            .enumerate()
+            .map(|(index, text)| text.parse().context(InvalidWord { index, text }))
+            .collect::<Result<_, _>>()?;
+        Ok(Program { words })
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, word) in self.words.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}", word)?;
+        }
+        Ok(())
+    }
+}
+
+impl Deref for Program {
+    type Target = [isize];
+
+    fn deref(&self) -> &[isize] {
+        &self.words
+    }
+}
+
+impl DerefMut for Program {
+    fn deref_mut(&mut self) -> &mut [isize] {
+        &mut self.words
+    }
+}
+
+impl From<Vec<isize>> for Program {
+    fn from(words: Vec<isize>) -> Self {
+        Program { words }
+    }
+}
+
+/// Creates a machine with buffers for its input and output.
+impl From<Program> for Machine {
+    fn from(program: Program) -> Self {
+        program.into_machine(VecDeque::new(), VecDeque::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tolerates_whitespace() {
+        let program: Program = " 1002, 4,3 ,\n4,\t33\n\n".parse().unwrap();
+
+        assert_eq!(&[1002, 4, 3, 4, 33], &program[..]);
+        assert_eq!("1002,4,3,4,33", program.to_string());
+    }
+
+    #[test]
+    fn reports_invalid_words() {
+        let error = "1,2,x3,4".parse::<Program>().unwrap_err();
+        assert!(matches!(&error, Error::InvalidWord { index: 2, text, .. } if text == "x3"));
+        assert_eq!(
+            "invalid word 2 'x3': invalid digit found in string",
+            error.to_string()
+        );
+
+        assert!(matches!(
+            "1,2,".parse::<Program>(),
+            Err(Error::InvalidWord { index: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn converts_into_machines() {
+        let mut program: Program = "1,0,0,0,99".parse().unwrap();
+        program[1] = 4;
+
+        let mut machine = Machine::from(program.clone());
+        assert_eq!(100, machine.run().unwrap());
+        assert_eq!(4, program.machine((), ()).peek(1));
+        assert_eq!(vec![1, 4, 0, 0, 99], program.into_words());
+    }
+}
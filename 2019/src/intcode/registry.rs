@@ -0,0 +1,528 @@
+//! The operations a machine executes, keyed by opcode.
+//!
+//! A [`Registry`] maps each opcode to an [`Operation`]: its mnemonic, how many arguments it
+//! takes and a handler executing it. The standard instruction set is registered like any other
+//! operation, and further opcodes such as a debug print or a call into the host are added with
+//! [`Registry::register`] and installed with
+//! [`Machine::set_registry`](super::Machine::set_registry).
+//!
+//! Handlers access their arguments through a [`Call`], which resolves modes like the standard
+//! instructions do, and tell the machine how to continue with an [`Effect`].
+
+use super::{
+    to_addr, AddressOverflow, Input, Machine, Mode, Opcode, Output, Overflow, OverflowPolicy,
+    Result, Word, MAX_INSTRUCTION_LEN,
+};
+use snafu::{ensure, OptionExt as _, Snafu};
+use std::fmt;
+use std::task::Poll;
+
+/// The number of opcodes an instruction can encode.
+const OPCODES: usize = 100;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("opcode {} does not fit in two digits", opcode))]
+    OpcodeOutOfRange { opcode: u8 },
+    #[snafu(display("opcode {} is already registered as {}", opcode, mnemonic))]
+    OpcodeTaken { opcode: u8, mnemonic: &'static str },
+    #[snafu(display("{} takes {} arguments, at most 3 are supported", mnemonic, arg_count))]
+    TooManyArguments {
+        mnemonic: &'static str,
+        arg_count: usize,
+    },
+}
+
+/// Executes an operation, see [`Operation`].
+pub type Handler<I, O, W> = fn(&mut Call<'_, I, O, W>) -> Result<Effect<W>>;
+
+/// How a machine continues after an operation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Effect<W = isize> {
+    /// Continue with the instruction after the arguments.
+    Continue,
+    /// Continue at the given address.
+    Jump(usize),
+    /// The value was written to the output, continue with the instruction after the arguments.
+    Output(W),
+    /// Nothing was executed, the instruction is retried once input is available.
+    NeedsInput,
+    /// Nothing was executed, the instruction is retried once the output accepts a value.
+    OutputBlocked,
+    /// Halt with the value at address 0.
+    Halt,
+}
+
+/// An operation which can be assigned an opcode.
+pub struct Operation<I, O, W = isize> {
+    /// The name of the operation in traces.
+    pub mnemonic: &'static str,
+    pub arg_count: usize,
+    pub handler: Handler<I, O, W>,
+}
+
+impl<I, O, W> Clone for Operation<I, O, W> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<I, O, W> Copy for Operation<I, O, W> {}
+
+impl<I, O, W> fmt::Debug for Operation<I, O, W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Operation")
+            .field("mnemonic", &self.mnemonic)
+            .field("arg_count", &self.arg_count)
+            .finish()
+    }
+}
+
+/// The operations a machine can execute.
+pub struct Registry<I, O, W = isize> {
+    operations: Vec<Option<Operation<I, O, W>>>,
+    standard: bool,
+}
+
+impl<I, O, W> Registry<I, O, W> {
+    /// Creates a registry without any operation, not even the standard ones.
+    pub fn empty() -> Self {
+        Registry {
+            operations: vec![None; OPCODES],
+            standard: false,
+        }
+    }
+
+    /// Assigns an opcode to an operation, which fails if the opcode is taken.
+    pub fn register(&mut self, opcode: u8, operation: Operation<I, O, W>) -> Result<(), Error> {
+        ensure!(usize::from(opcode) < OPCODES, OpcodeOutOfRange { opcode });
+        ensure!(
+            operation.arg_count < MAX_INSTRUCTION_LEN,
+            TooManyArguments {
+                mnemonic: operation.mnemonic,
+                arg_count: operation.arg_count
+            }
+        );
+        let slot = &mut self.operations[usize::from(opcode)];
+        if let Some(taken) = slot {
+            return OpcodeTaken {
+                opcode,
+                mnemonic: taken.mnemonic,
+            }
+            .fail();
+        }
+        *slot = Some(operation);
+        Ok(())
+    }
+
+    pub fn get(&self, opcode: u8) -> Option<&Operation<I, O, W>> {
+        self.operations.get(usize::from(opcode))?.as_ref()
+    }
+
+    /// The registered opcodes together with their operations, in ascending order.
+    pub fn operations(&self) -> impl Iterator<Item = (u8, &Operation<I, O, W>)> {
+        (0..)
+            .zip(&self.operations)
+            .filter_map(|(opcode, operation)| {
+                operation.as_ref().map(|operation| (opcode, operation))
+            })
+    }
+
+    /// Whether the standard opcodes execute the standard operations.
+    pub(super) fn is_standard(&self) -> bool {
+        self.standard
+    }
+}
+
+impl<I: Input<W>, O: Output<W>, W: Word> Registry<I, O, W> {
+    /// Creates a registry with the standard instruction set.
+    pub fn standard() -> Self {
+        let mut registry = Registry::empty();
+        for &opcode in &[
+            Opcode::Add,
+            Opcode::Mul,
+            Opcode::Input,
+            Opcode::Output,
+            Opcode::JumpNotZero,
+            Opcode::JumpZero,
+            Opcode::LessThan,
+            Opcode::Equal,
+            Opcode::OffsetRBase,
+            Opcode::Halt,
+        ] {
+            registry
+                .register(opcode.into(), builtin(opcode))
+                .expect("standard opcodes are distinct");
+        }
+        // Opcodes are never unregistered, hence the standard ones stay as they are
+        registry.standard = true;
+        registry
+    }
+}
+
+impl<I: Input<W>, O: Output<W>, W: Word> Default for Registry<I, O, W> {
+    fn default() -> Self {
+        Registry::standard()
+    }
+}
+
+impl<I, O, W> fmt::Debug for Registry<I, O, W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map()
+            .entries(
+                self.operations()
+                    .map(|(opcode, operation)| (opcode, operation.mnemonic)),
+            )
+            .finish()
+    }
+}
+
+/// The instruction being executed, as seen by its handler.
+pub struct Call<'a, I, O, W> {
+    machine: &'a mut Machine<I, O, W>,
+    modes: [Mode; 3],
+}
+
+impl<'a, I: Input<W>, O: Output<W>, W: Word> Call<'a, I, O, W> {
+    pub(super) fn new(machine: &'a mut Machine<I, O, W>, modes: [Mode; 3]) -> Self {
+        Call { machine, modes }
+    }
+
+    /// The address of the instruction.
+    pub fn pc(&self) -> usize {
+        self.machine.pc()
+    }
+
+    /// The modes of the arguments, including ones the operation does not take.
+    pub fn modes(&self) -> &[Mode; 3] {
+        &self.modes
+    }
+
+    /// Loads the value of argument `i`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `i` is not below 3.
+    pub fn load(&mut self, i: usize) -> Result<W> {
+        self.machine.load(&self.modes, i)
+    }
+
+    /// Stores a value where argument `i` refers to, see [`Call::load`].
+    pub fn store(&mut self, i: usize, value: W) -> Result<()> {
+        self.machine.store(&self.modes, i, value)
+    }
+
+    /// Fails like [`Call::store`] would for argument `i`, without storing anything, e.g. to
+    /// check where an input goes before consuming it.
+    pub fn check_store(&self, i: usize) -> Result<()> {
+        self.machine.check_store(&self.modes, i)
+    }
+
+    pub fn relative_base(&self) -> isize {
+        self.machine.relative_base()
+    }
+
+    pub fn set_relative_base(&mut self, relative_base: isize) {
+        self.machine.relative_base = relative_base;
+    }
+
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.machine.overflow
+    }
+
+    /// Reads a value from the input, which unlike reading through [`Call::input_mut`] is
+    /// recorded in the history and session of the machine.
+    pub fn read_input(&mut self) -> Result<Poll<W>> {
+        let value = self.machine.input.read()?;
+        if let Poll::Ready(value) = &value {
+            if self.machine.history.is_some() || self.machine.session.is_some() {
+                self.machine.consumed_input = Some(value.clone());
+            }
+        }
+        Ok(value)
+    }
+
+    pub fn input_mut(&mut self) -> &mut I {
+        self.machine.input_mut()
+    }
+
+    pub fn output_mut(&mut self) -> &mut O {
+        self.machine.output_mut()
+    }
+
+    pub fn machine(&self) -> &Machine<I, O, W> {
+        self.machine
+    }
+}
+
+/// The operation executing a standard opcode.
+pub(super) fn builtin<I: Input<W>, O: Output<W>, W: Word>(opcode: Opcode) -> Operation<I, O, W> {
+    let handler: Handler<I, O, W> = match opcode {
+        Opcode::Add => add,
+        Opcode::Mul => mul,
+        Opcode::Input => input,
+        Opcode::Output => output,
+        Opcode::JumpNotZero => jump_not_zero,
+        Opcode::JumpZero => jump_zero,
+        Opcode::LessThan => less_than,
+        Opcode::Equal => equal,
+        Opcode::OffsetRBase => offset_rbase,
+        Opcode::Halt => halt,
+    };
+    Operation {
+        mnemonic: opcode.mnemonic(),
+        arg_count: opcode.arg_count(),
+        handler,
+    }
+}
+
+fn add<I: Input<W>, O: Output<W>, W: Word>(call: &mut Call<I, O, W>) -> Result<Effect<W>> {
+    let (lhs, rhs) = (call.load(0)?, call.load(1)?);
+    let value = call
+        .overflow_policy()
+        .add(&lhs, &rhs)
+        .context(Overflow { pc: call.pc() })?;
+    call.store(2, value)?;
+    Ok(Effect::Continue)
+}
+
+fn mul<I: Input<W>, O: Output<W>, W: Word>(call: &mut Call<I, O, W>) -> Result<Effect<W>> {
+    let (lhs, rhs) = (call.load(0)?, call.load(1)?);
+    let value = call
+        .overflow_policy()
+        .mul(&lhs, &rhs)
+        .context(Overflow { pc: call.pc() })?;
+    call.store(2, value)?;
+    Ok(Effect::Continue)
+}
+
+fn input<I: Input<W>, O: Output<W>, W: Word>(call: &mut Call<I, O, W>) -> Result<Effect<W>> {
+    call.check_store(0)?;
+    match call.read_input()? {
+        Poll::Ready(value) => {
+            call.store(0, value)?;
+            Ok(Effect::Continue)
+        }
+        Poll::Pending => Ok(Effect::NeedsInput),
+    }
+}
+
+fn output<I: Input<W>, O: Output<W>, W: Word>(call: &mut Call<I, O, W>) -> Result<Effect<W>> {
+    let value = call.load(0)?;
+    match call.output_mut().write(value.clone())? {
+        Poll::Ready(()) => Ok(Effect::Output(value)),
+        Poll::Pending => Ok(Effect::OutputBlocked),
+    }
+}
+
+fn jump_not_zero<I: Input<W>, O: Output<W>, W: Word>(
+    call: &mut Call<I, O, W>,
+) -> Result<Effect<W>> {
+    if !call.load(0)?.is_zero() {
+        Ok(Effect::Jump(to_addr(&call.load(1)?)?))
+    } else {
+        Ok(Effect::Continue)
+    }
+}
+
+fn jump_zero<I: Input<W>, O: Output<W>, W: Word>(call: &mut Call<I, O, W>) -> Result<Effect<W>> {
+    if call.load(0)?.is_zero() {
+        Ok(Effect::Jump(to_addr(&call.load(1)?)?))
+    } else {
+        Ok(Effect::Continue)
+    }
+}
+
+fn less_than<I: Input<W>, O: Output<W>, W: Word>(call: &mut Call<I, O, W>) -> Result<Effect<W>> {
+    let value = call.load(0)? < call.load(1)?;
+    call.store(2, W::from_isize(value as isize))?;
+    Ok(Effect::Continue)
+}
+
+fn equal<I: Input<W>, O: Output<W>, W: Word>(call: &mut Call<I, O, W>) -> Result<Effect<W>> {
+    let value = call.load(0)? == call.load(1)?;
+    call.store(2, W::from_isize(value as isize))?;
+    Ok(Effect::Continue)
+}
+
+fn offset_rbase<I: Input<W>, O: Output<W>, W: Word>(call: &mut Call<I, O, W>) -> Result<Effect<W>> {
+    let offset = call.load(0)?.to_isize();
+    let relative_base = offset
+        .and_then(|offset| call.relative_base().checked_add(offset))
+        .context(AddressOverflow)?;
+    call.set_relative_base(relative_base);
+    Ok(Effect::Continue)
+}
+
+fn halt<I: Input<W>, O: Output<W>, W: Word>(_: &mut Call<I, O, W>) -> Result<Effect<W>> {
+    Ok(Effect::Halt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Error as MachineError, FastMachine, Fault, Session, State, Trace};
+    use super::*;
+    use std::collections::VecDeque;
+    use std::sync::Arc;
+
+    type Test = Machine<VecDeque<isize>, Vec<isize>>;
+
+    /// Stores the square of its first argument where its second refers to.
+    fn square(call: &mut Call<VecDeque<isize>, Vec<isize>, isize>) -> Result<Effect> {
+        let value = call.load(0)?;
+        call.store(1, value * value)?;
+        Ok(Effect::Continue)
+    }
+
+    /// Stores its first argument where its second refers to.
+    fn set(call: &mut Call<VecDeque<isize>, Vec<isize>, isize>) -> Result<Effect> {
+        let value = call.load(0)?;
+        call.store(1, value)?;
+        Ok(Effect::Continue)
+    }
+
+    /// Stores an input where its argument refers to, without checking the address first.
+    fn careless_input(call: &mut Call<VecDeque<isize>, Vec<isize>, isize>) -> Result<Effect> {
+        if let Poll::Ready(value) = call.read_input()? {
+            call.store(0, value)?;
+        }
+        Ok(Effect::Continue)
+    }
+
+    fn with_registry(program: Vec<isize>, registry: Registry<VecDeque<isize>, Vec<isize>>) -> Test {
+        let mut machine = Machine::new(program, VecDeque::new(), Vec::new());
+        machine.set_registry(Arc::new(registry));
+        machine
+    }
+
+    #[test]
+    fn executes_registered_operations() {
+        let mut registry = Registry::standard();
+        let operation = Operation {
+            mnemonic: "SQR",
+            arg_count: 2,
+            handler: square,
+        };
+        registry.register(42, operation).unwrap();
+        // Squares 7 into 0, adds 1 to it and halts
+        let program = vec![142, 7, 0, 1001, 0, 1, 0, 99];
+
+        let mut machine = with_registry(program, registry);
+        let mut fast = FastMachine::new(machine.clone());
+        machine.start_trace(Trace::new());
+        assert_eq!(50, machine.run().unwrap());
+        assert_eq!(50, fast.run().unwrap());
+
+        let mnemonics: Vec<_> = machine
+            .trace()
+            .unwrap()
+            .records()
+            .iter()
+            .map(|record| record.mnemonic)
+            .collect();
+        assert_eq!(vec!["SQR", "ADD", "HALT"], mnemonics);
+    }
+
+    #[test]
+    fn fast_engine_sees_writes_of_registered_operations() {
+        let mut registry = Registry::standard();
+        let operation = Operation {
+            mnemonic: "SET",
+            arg_count: 2,
+            handler: set,
+        };
+        registry.register(42, operation).unwrap();
+        // Overwrites the already executed instruction at 0 with a halt and jumps back to it
+        let program = vec![1101, 1, 1, 20, 142, 99, 0, 1105, 1, 0];
+
+        let mut machine = with_registry(program, registry);
+        machine.set_step_limit(Some(100));
+        let mut fast = FastMachine::new(machine.clone());
+        assert_eq!(99, machine.run().unwrap());
+        assert_eq!(99, fast.run().unwrap());
+        assert_eq!(machine.steps(), fast.machine().steps());
+        assert!(fast.machine().accesses().is_empty());
+    }
+
+    #[test]
+    fn records_input_only_for_completed_steps() {
+        let mut registry = Registry::standard();
+        let operation = Operation {
+            mnemonic: "INX",
+            arg_count: 1,
+            handler: careless_input,
+        };
+        registry.register(42, operation).unwrap();
+        let mut machine = with_registry(vec![42, -5, 99], registry);
+        machine.input_mut().push_back(7);
+        machine.start_session(Session::new());
+
+        assert!(matches!(
+            machine.step(),
+            State::Faulted(Fault {
+                error: MachineError::NegativeAddress { addr: -5 },
+                ..
+            })
+        ));
+        assert!(machine.session().unwrap().events().is_empty());
+    }
+
+    #[test]
+    fn rejects_conflicting_operations() {
+        let mut registry: Registry<VecDeque<isize>, Vec<isize>> = Registry::standard();
+        let operation = |arg_count| Operation {
+            mnemonic: "SQR",
+            arg_count,
+            handler: square,
+        };
+
+        assert!(matches!(
+            registry.register(1, operation(2)),
+            Err(Error::OpcodeTaken {
+                opcode: 1,
+                mnemonic: "ADD"
+            })
+        ));
+        assert!(matches!(
+            registry.register(100, operation(2)),
+            Err(Error::OpcodeOutOfRange { opcode: 100 })
+        ));
+        assert!(matches!(
+            registry.register(42, operation(4)),
+            Err(Error::TooManyArguments { arg_count: 4, .. })
+        ));
+        assert_eq!(10, registry.operations().count());
+    }
+
+    #[test]
+    fn faults_on_unregistered_opcodes() {
+        let mut registry = Registry::empty();
+        registry.register(99, builtin(Opcode::Halt)).unwrap();
+        let mut machine = with_registry(vec![1, 0, 0, 0, 99], registry);
+        let mut fast = FastMachine::new(machine.clone());
+
+        assert!(matches!(
+            machine.run(),
+            Err(Fault {
+                error: MachineError::InvalidOpcode { instr: 1 },
+                ..
+            })
+        ));
+        assert!(matches!(
+            fast.run(),
+            Err(Fault {
+                error: MachineError::InvalidOpcode { instr: 1 },
+                ..
+            })
+        ));
+
+        let mut machine = with_registry(vec![42, 0, 0, 99], Registry::standard());
+        assert!(matches!(
+            machine.run(),
+            Err(Fault {
+                error: MachineError::InvalidOpcode { instr: 42 },
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,291 @@
+//! Recording and replaying the I/O of a machine, see
+//! [`Machine::start_session`](super::Machine::start_session).
+//!
+//! A [`Session`] lists every value a machine consumed from its input and wrote to its output,
+//! together with the step it happened at. Sessions are stored as text with one event per line,
+//! e.g. `12 in 1` or `15 out 0`. Replaying a session feeds the recorded input to a machine and
+//! fails at the first step where the machine does something else than recorded, which makes
+//! sessions usable as regression fixtures for the code driving a machine.
+
+use super::{Machine, Output, State};
+use snafu::{OptionExt as _, ResultExt as _, Snafu};
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("invalid event on line {}: '{}'", line, text))]
+    InvalidEvent { line: usize, text: String },
+    #[snafu(display("diverged at step {}: expected {}, got {}", step, expected, actual))]
+    Diverged {
+        step: usize,
+        expected: String,
+        actual: String,
+    },
+    #[snafu(display("machine faulted: {}", source))]
+    Fault { source: super::Fault },
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A value which crossed the I/O boundary of a machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event<W = isize> {
+    /// The instruction executed at `step` consumed `value` from the input.
+    Input { step: usize, value: W },
+    /// The instruction executed at `step` wrote `value` to the output.
+    Output { step: usize, value: W },
+}
+
+impl<W> Event<W> {
+    pub fn step(&self) -> usize {
+        match *self {
+            Event::Input { step, .. } | Event::Output { step, .. } => step,
+        }
+    }
+}
+
+impl<W: fmt::Display> fmt::Display for Event<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Input { step, value } => write!(f, "{} in {}", step, value),
+            Event::Output { step, value } => write!(f, "{} out {}", step, value),
+        }
+    }
+}
+
+/// The I/O of a machine in the order it happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session<W = isize> {
+    events: Vec<Event<W>>,
+}
+
+impl<W> Default for Session<W> {
+    fn default() -> Self {
+        Session::new()
+    }
+}
+
+impl<W> Session<W> {
+    pub fn new() -> Self {
+        Session { events: Vec::new() }
+    }
+
+    pub fn events(&self) -> &[Event<W>] {
+        &self.events
+    }
+
+    pub(super) fn push(&mut self, event: Event<W>) {
+        self.events.push(event);
+    }
+}
+
+impl<W: Clone + PartialEq + fmt::Display> Session<W> {
+    /// Checks that `actual` did the same I/O as this session, e.g. after the code driving the
+    /// machine changed.
+    pub fn compare(&self, actual: &Session<W>) -> Result<()> {
+        let mut expected = self.events.iter();
+        let mut actual = actual.events.iter();
+        loop {
+            match (expected.next(), actual.next()) {
+                (None, None) => break Ok(()),
+                (Some(e), Some(a)) if e == a => {}
+                (e, a) => {
+                    let step = a.or(e).map_or(0, Event::step);
+                    break Diverged {
+                        step,
+                        expected: describe(e),
+                        actual: describe(a),
+                    }
+                    .fail();
+                }
+            }
+        }
+    }
+
+    /// Runs `machine` until it halts, feeding it the recorded input whenever it needs input and
+    /// checking every output against the recording. Returns the value at address 0.
+    ///
+    /// The machine has to start where the recording started with an empty input, since values
+    /// already queued are consumed without being checked.
+    pub fn replay<O: Output<W>>(&self, machine: &mut Machine<VecDeque<W>, O, W>) -> Result<W>
+    where
+        W: super::Word,
+    {
+        let mut events = self.events.iter();
+        let mut next = events.next();
+        loop {
+            let (step, actual) = match machine.run_until_event() {
+                State::Running => continue,
+                State::NeedsInput => match next {
+                    Some(Event::Input { step, value }) if *step == machine.steps() => {
+                        machine.input_mut().push_back(value.clone());
+                        next = events.next();
+                        continue;
+                    }
+                    _ => (machine.steps(), "an input request".to_string()),
+                },
+                State::Output(value) => {
+                    let step = machine.steps() - 1;
+                    let actual = Event::Output { step, value };
+                    if next == Some(&actual) {
+                        next = events.next();
+                        continue;
+                    }
+                    (step, describe(Some(&actual)))
+                }
+                State::OutputBlocked => {
+                    let fault = machine.fault(super::Error::OutputFull);
+                    return Err(fault).context(Fault);
+                }
+                State::Halted(value) => match next {
+                    None => return Ok(value),
+                    Some(_) => (machine.steps() - 1, "a halt".to_string()),
+                },
+                State::Faulted(fault) => return Err(fault).context(Fault),
+            };
+            return Diverged {
+                step,
+                expected: describe(next),
+                actual,
+            }
+            .fail();
+        }
+    }
+}
+
+fn describe<W: fmt::Display>(event: Option<&Event<W>>) -> String {
+    match event {
+        Some(event) => format!("'{}'", event),
+        None => "the end of the session".to_string(),
+    }
+}
+
+/// Writes one event per line.
+impl<W: fmt::Display> fmt::Display for Session<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.events
+            .iter()
+            .try_for_each(|event| writeln!(f, "{}", event))
+    }
+}
+
+impl<W: FromStr> FromStr for Session<W> {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let events = s
+            .lines()
+            .enumerate()
+            .filter(|(_, text)| !text.trim().is_empty())
+            .map(|(i, text)| {
+                parse_event(text).context(InvalidEvent {
+                    line: i + 1,
+                    text: text.trim(),
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Session { events })
+    }
+}
+
+fn parse_event<W: FromStr>(text: &str) -> Option<Event<W>> {
+    let mut words = text.split_whitespace();
+    let step = words.next()?.parse().ok()?;
+    let kind = words.next()?;
+    let value = words.next()?.parse().ok()?;
+    if words.next().is_some() {
+        return None;
+    }
+    match kind {
+        "in" => Some(Event::Input { step, value }),
+        "out" => Some(Event::Output { step, value }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm::assemble;
+
+    /// Outputs twice each input until it reads zero
+    const DOUBLER: &str = "
+        IN [15]
+        JZ [15], #14
+        MUL [15], #2, [15]
+        OUT [15]
+        JZ #0, #0
+        HALT
+        DATA 0";
+
+    fn record(input: Vec<isize>) -> Session {
+        let mut machine = Machine::new(
+            assemble(DOUBLER).unwrap(),
+            VecDeque::from(input),
+            VecDeque::new(),
+        );
+        machine.start_session(Session::new());
+        machine.run().unwrap();
+        machine.stop_session().unwrap()
+    }
+
+    fn replay(session: &Session) -> Result<isize> {
+        let mut machine =
+            Machine::new(assemble(DOUBLER).unwrap(), VecDeque::new(), VecDeque::new());
+        session.replay(&mut machine)
+    }
+
+    #[test]
+    fn records_io_with_steps() {
+        let session = record(vec![3, 0]);
+
+        assert_eq!(
+            &[
+                Event::Input { step: 0, value: 3 },
+                Event::Output { step: 3, value: 6 },
+                Event::Input { step: 5, value: 0 },
+            ],
+            session.events()
+        );
+        assert_eq!("0 in 3\n3 out 6\n5 in 0\n", session.to_string());
+        assert_eq!(session, session.to_string().parse().unwrap());
+        assert!(matches!(
+            "0 in 3\n\n3 put 6".parse::<Session>(),
+            Err(Error::InvalidEvent { line: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn replays_sessions() {
+        let session = record(vec![3, 4, 0]);
+        replay(&session).unwrap();
+
+        let diverged: Session = "0 in 3\n3 out 7\n".parse().unwrap();
+        let error = replay(&diverged).unwrap_err();
+        assert!(matches!(error, Error::Diverged { step: 3, .. }));
+        assert_eq!(
+            "diverged at step 3: expected '3 out 7', got '3 out 6'",
+            error.to_string()
+        );
+
+        let truncated: Session = "0 in 3\n3 out 6\n".parse().unwrap();
+        assert!(matches!(
+            replay(&truncated),
+            Err(Error::Diverged { step: 5, .. })
+        ));
+    }
+
+    #[test]
+    fn compares_sessions() {
+        let session = record(vec![3, 4, 0]);
+        assert!(session.compare(&record(vec![3, 4, 0])).is_ok());
+
+        let error = session.compare(&record(vec![3, 5, 0])).unwrap_err();
+        assert_eq!(
+            "diverged at step 5: expected '5 in 4', got '5 in 5'",
+            error.to_string()
+        );
+    }
+}
@@ -0,0 +1,538 @@
+//! Symbolic execution of intcode programs.
+//!
+//! Chosen memory cells are replaced by variables and the program is executed on expressions over
+//! them instead of on numbers, which answers questions like "which inputs make the program halt
+//! with X" without trying every input:
+//!
+//! - [`execute`] runs a program symbolically, which works as long as control flow, written
+//!   addresses and instructions do not depend on a variable.
+//! - [`Expr::linear`] turns an expression into a [`Linear`] form if it is linear in the variables,
+//!   which [`Linear::solve`] solves for a target value.
+//! - [`solve`] combines both and falls back to trying every input on a [`Machine`] when the
+//!   program cannot be executed symbolically or the result is not linear.
+
+use super::{Instruction, Machine, Mode, Opcode};
+use snafu::{ResultExt as _, Snafu};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("instruction at {} depends on a variable", pc))]
+    SymbolicInstruction { pc: usize },
+    #[snafu(display("branch at {} depends on a variable", pc))]
+    SymbolicBranch { pc: usize },
+    #[snafu(display("address written at {} depends on a variable", pc))]
+    SymbolicWrite { pc: usize },
+    #[snafu(display("relative base offset at {} depends on a variable", pc))]
+    SymbolicOffset { pc: usize },
+    #[snafu(display("input at {} cannot be executed symbolically", pc))]
+    Input { pc: usize },
+    #[snafu(display("instruction at {} faulted: {}", pc, source))]
+    Fault { pc: usize, source: super::Error },
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The number of steps after which a program is assumed to run forever, both when executing it
+/// symbolically and when trying inputs.
+pub const MAX_STEPS: usize = 1_000_000;
+
+/// A value computed by a program, in terms of the initial values of the variables.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Const(isize),
+    /// The initial value of the cell at the given address.
+    Var(usize),
+    Add(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    LessThan(Box<Expr>, Box<Expr>),
+    Equal(Box<Expr>, Box<Expr>),
+    /// The value at an address which depends on a variable.
+    Load(Box<Expr>),
+}
+
+impl Expr {
+    fn add(lhs: Expr, rhs: Expr) -> Expr {
+        match (lhs, rhs) {
+            (Expr::Const(a), Expr::Const(b)) if a.checked_add(b).is_some() => Expr::Const(a + b),
+            (Expr::Const(0), expr) | (expr, Expr::Const(0)) => expr,
+            (lhs, rhs) => Expr::Add(Box::new(lhs), Box::new(rhs)),
+        }
+    }
+
+    fn mul(lhs: Expr, rhs: Expr) -> Expr {
+        match (lhs, rhs) {
+            (Expr::Const(a), Expr::Const(b)) if a.checked_mul(b).is_some() => Expr::Const(a * b),
+            (Expr::Const(0), _) | (_, Expr::Const(0)) => Expr::Const(0),
+            (Expr::Const(1), expr) | (expr, Expr::Const(1)) => expr,
+            (lhs, rhs) => Expr::Mul(Box::new(lhs), Box::new(rhs)),
+        }
+    }
+
+    fn less_than(lhs: Expr, rhs: Expr) -> Expr {
+        match (lhs, rhs) {
+            (Expr::Const(a), Expr::Const(b)) => Expr::Const((a < b) as isize),
+            (lhs, rhs) => Expr::LessThan(Box::new(lhs), Box::new(rhs)),
+        }
+    }
+
+    fn equal(lhs: Expr, rhs: Expr) -> Expr {
+        match (lhs, rhs) {
+            (Expr::Const(a), Expr::Const(b)) => Expr::Const((a == b) as isize),
+            (lhs, rhs) => Expr::Equal(Box::new(lhs), Box::new(rhs)),
+        }
+    }
+
+    /// The expression as a sum of variables times coefficients plus a constant, if it is one.
+    pub fn linear(&self) -> Option<Linear> {
+        match self {
+            Expr::Const(value) => Some(Linear {
+                constant: *value as i128,
+                terms: BTreeMap::new(),
+            }),
+            Expr::Var(addr) => Some(Linear {
+                constant: 0,
+                terms: vec![(*addr, 1)].into_iter().collect(),
+            }),
+            Expr::Add(lhs, rhs) => {
+                let (mut lhs, rhs) = (lhs.linear()?, rhs.linear()?);
+                lhs.constant = narrow(lhs.constant + rhs.constant)?;
+                for (addr, coefficient) in rhs.terms {
+                    let term = lhs.terms.entry(addr).or_insert(0);
+                    *term = narrow(*term + coefficient)?;
+                }
+                lhs.terms.retain(|_, coefficient| *coefficient != 0);
+                Some(lhs)
+            }
+            Expr::Mul(lhs, rhs) => {
+                let (lhs, rhs) = (lhs.linear()?, rhs.linear()?);
+                let (factor, mut linear) = match (lhs.terms.is_empty(), rhs.terms.is_empty()) {
+                    (true, _) => (lhs.constant, rhs),
+                    (_, true) => (rhs.constant, lhs),
+                    _ => return None,
+                };
+                linear.constant = narrow(linear.constant * factor)?;
+                for coefficient in linear.terms.values_mut() {
+                    *coefficient = narrow(*coefficient * factor)?;
+                }
+                linear.terms.retain(|_, coefficient| *coefficient != 0);
+                Some(linear)
+            }
+            Expr::LessThan(..) | Expr::Equal(..) | Expr::Load(_) => None,
+        }
+    }
+}
+
+/// Keeps linear forms within `isize`, so that solving them cannot overflow.
+fn narrow(value: i128) -> Option<i128> {
+    isize::try_from(value).ok().map(|value| value as i128)
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Const(value) => write!(f, "{}", value),
+            Expr::Var(addr) => write!(f, "[{}]", addr),
+            Expr::Add(lhs, rhs) => write!(f, "({} + {})", lhs, rhs),
+            Expr::Mul(lhs, rhs) => write!(f, "({} * {})", lhs, rhs),
+            Expr::LessThan(lhs, rhs) => write!(f, "({} < {})", lhs, rhs),
+            Expr::Equal(lhs, rhs) => write!(f, "({} == {})", lhs, rhs),
+            Expr::Load(addr) => write!(f, "[{}]", addr),
+        }
+    }
+}
+
+/// A linear expression `constant + sum(coefficient * [addr])`, see [`Expr::linear`].
+///
+/// The constant and the coefficients are wider than a word, but always fit in an `isize`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Linear {
+    pub constant: i128,
+    /// The coefficient of each variable, none of which is zero.
+    pub terms: BTreeMap<usize, i128>,
+}
+
+impl Linear {
+    /// Finds values for the variables within their domains for which the expression equals
+    /// `target`, returned in the order of `domains`.
+    ///
+    /// Variables are assigned in order of decreasing coefficient, and only values which leave the
+    /// remainder within reach of the other variables are tried, hence the last variable is always
+    /// solved for directly.
+    pub fn solve(
+        &self,
+        domains: &[(usize, RangeInclusive<isize>)],
+        target: isize,
+    ) -> Option<Vec<isize>> {
+        let mut vars: Vec<_> = domains
+            .iter()
+            .enumerate()
+            .map(|(i, (addr, domain))| {
+                let coefficient = self.terms.get(addr).copied().unwrap_or(0);
+                (
+                    i,
+                    coefficient,
+                    *domain.start() as i128,
+                    *domain.end() as i128,
+                )
+            })
+            .collect();
+        vars.sort_by_key(|&(_, coefficient, _, _)| std::cmp::Reverse(coefficient.abs()));
+
+        let mut values = vec![0; vars.len()];
+        if search(&vars, target as i128 - self.constant, &mut values) {
+            Some(values)
+        } else {
+            None
+        }
+    }
+}
+
+/// Assigns `vars` such that the sum of their terms equals `remainder`.
+fn search(vars: &[(usize, i128, i128, i128)], remainder: i128, values: &mut [isize]) -> bool {
+    let (&(i, coefficient, lo, hi), rest) = match vars.split_first() {
+        Some(split) => split,
+        None => return remainder == 0,
+    };
+    if lo > hi {
+        return false;
+    }
+
+    // The range of values the other variables can add up to
+    let (rest_min, rest_max) = rest
+        .iter()
+        .fold((0i128, 0i128), |(min, max), &(_, c, lo, hi)| {
+            let (a, b) = (c * lo, c * hi);
+            (min.saturating_add(a.min(b)), max.saturating_add(a.max(b)))
+        });
+    let (lo, hi) = match coefficient {
+        0 => (lo, hi),
+        c if c > 0 => (
+            lo.max(div_ceil(remainder - rest_max, c)),
+            hi.min((remainder - rest_min).div_euclid(c)),
+        ),
+        c => (
+            lo.max(div_ceil(rest_min - remainder, -c)),
+            hi.min((rest_max - remainder).div_euclid(-c)),
+        ),
+    };
+
+    for value in lo..=hi {
+        values[i] = value as isize;
+        if search(rest, remainder - coefficient * value, values) {
+            return true;
+        }
+    }
+    false
+}
+
+fn div_ceil(a: i128, b: i128) -> i128 {
+    -(-a).div_euclid(b)
+}
+
+/// The final state of a symbolically executed program, see [`execute`].
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    memory: BTreeMap<usize, Expr>,
+    outputs: Vec<Expr>,
+}
+
+impl Outcome {
+    /// The final value at an address.
+    pub fn get(&self, addr: usize) -> Expr {
+        self.memory.get(&addr).cloned().unwrap_or(Expr::Const(0))
+    }
+
+    pub fn outputs(&self) -> &[Expr] {
+        &self.outputs
+    }
+}
+
+struct Executor {
+    pc: usize,
+    relative_base: isize,
+    memory: BTreeMap<usize, Expr>,
+    outputs: Vec<Expr>,
+}
+
+impl Executor {
+    fn get(&self, addr: usize) -> Expr {
+        self.memory.get(&addr).cloned().unwrap_or(Expr::Const(0))
+    }
+
+    /// The address argument `i` refers to, which is `None` if it depends on a variable.
+    fn arg_addr(&self, instr: &Instruction, i: usize) -> Result<Option<usize>> {
+        let pc = self.pc;
+        let addr = pc + 1 + i;
+        let base = match instr.modes[i] {
+            Mode::Immidiate => return Ok(Some(addr)),
+            Mode::Position => 0,
+            Mode::Relative => self.relative_base,
+        };
+        match self.get(addr) {
+            Expr::Const(offset) => offset
+                .checked_add(base)
+                .ok_or(super::Error::AddressOverflow)
+                .and_then(|addr| super::to_addr(&addr))
+                .map(Some)
+                .context(Fault { pc }),
+            _ => Ok(None),
+        }
+    }
+
+    fn load(&self, instr: &Instruction, i: usize) -> Result<Expr> {
+        Ok(match self.arg_addr(instr, i)? {
+            Some(addr) => self.get(addr),
+            None => {
+                let base = match instr.modes[i] {
+                    Mode::Relative => self.relative_base,
+                    _ => 0,
+                };
+                Expr::Load(Box::new(Expr::add(
+                    self.get(self.pc + 1 + i),
+                    Expr::Const(base),
+                )))
+            }
+        })
+    }
+
+    fn store(&mut self, instr: &Instruction, i: usize, value: Expr) -> Result<()> {
+        let pc = self.pc;
+        let addr = self
+            .arg_addr(instr, i)?
+            .ok_or(Error::SymbolicWrite { pc })?;
+        self.memory.insert(addr, value);
+        Ok(())
+    }
+
+    fn jump_if(&mut self, instr: &Instruction, jump: impl Fn(isize) -> bool) -> Result<()> {
+        let pc = self.pc;
+        match (self.load(instr, 0)?, self.load(instr, 1)?) {
+            (Expr::Const(condition), _) if !jump(condition) => self.pc += 3,
+            (Expr::Const(_), Expr::Const(target)) => {
+                self.pc = super::to_addr(&target).context(Fault { pc })?
+            }
+            _ => return SymbolicBranch { pc }.fail(),
+        }
+        Ok(())
+    }
+
+    /// Executes a single instruction, returning false once the program halted.
+    fn step(&mut self) -> Result<bool> {
+        let pc = self.pc;
+        let instr = match self.get(pc) {
+            Expr::Const(word) => Instruction::try_from(word).context(Fault { pc })?,
+            _ => return SymbolicInstruction { pc }.fail(),
+        };
+
+        let next = pc + instr.opcode.arg_count() + 1;
+        match instr.opcode {
+            Opcode::Add | Opcode::Mul | Opcode::LessThan | Opcode::Equal => {
+                let (lhs, rhs) = (self.load(&instr, 0)?, self.load(&instr, 1)?);
+                let value = match instr.opcode {
+                    Opcode::Add => Expr::add(lhs, rhs),
+                    Opcode::Mul => Expr::mul(lhs, rhs),
+                    Opcode::LessThan => Expr::less_than(lhs, rhs),
+                    _ => Expr::equal(lhs, rhs),
+                };
+                self.store(&instr, 2, value)?;
+                self.pc = next;
+            }
+            Opcode::Input => return Input { pc }.fail(),
+            Opcode::Output => {
+                let value = self.load(&instr, 0)?;
+                self.outputs.push(value);
+                self.pc = next;
+            }
+            Opcode::JumpNotZero => self.jump_if(&instr, |condition| condition != 0)?,
+            Opcode::JumpZero => self.jump_if(&instr, |condition| condition == 0)?,
+            Opcode::OffsetRBase => match self.load(&instr, 0)? {
+                Expr::Const(offset) => {
+                    self.relative_base = self
+                        .relative_base
+                        .checked_add(offset)
+                        .ok_or(super::Error::AddressOverflow)
+                        .context(Fault { pc })?;
+                    self.pc = next;
+                }
+                _ => return SymbolicOffset { pc }.fail(),
+            },
+            Opcode::Halt => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+/// Executes `program` until it halts, with the cells at the addresses in `vars` replaced by
+/// variables.
+///
+/// Fails with `super::Error::BudgetExceeded` after [`MAX_STEPS`] steps.
+pub fn execute(program: &[isize], vars: &[usize]) -> Result<Outcome> {
+    let mut memory: BTreeMap<_, _> = program
+        .iter()
+        .enumerate()
+        .map(|(addr, &value)| (addr, Expr::Const(value)))
+        .collect();
+    for &addr in vars {
+        memory.insert(addr, Expr::Var(addr));
+    }
+
+    let mut executor = Executor {
+        pc: 0,
+        relative_base: 0,
+        memory,
+        outputs: Vec::new(),
+    };
+    for steps in 0.. {
+        if steps == MAX_STEPS {
+            let pc = executor.pc;
+            return Err(Error::Fault {
+                pc,
+                source: super::Error::BudgetExceeded { steps, pc },
+            });
+        }
+        if !executor.step()? {
+            break;
+        }
+    }
+    Ok(Outcome {
+        memory: executor.memory,
+        outputs: executor.outputs,
+    })
+}
+
+/// Finds values for the cells at the addresses in `domains` for which `program` halts with
+/// `target` at address 0, returned in the order of `domains`.
+///
+/// Solves analytically when the final value at address 0 is linear in the cells and the solution
+/// halts with `target` on a machine, otherwise every combination of values is run until one
+/// matches. Combinations which run for more than
+/// [`MAX_STEPS`] steps are skipped.
+pub fn solve(
+    program: &[isize],
+    domains: &[(usize, RangeInclusive<isize>)],
+    target: isize,
+) -> Option<Vec<isize>> {
+    let vars: Vec<_> = domains.iter().map(|(addr, _)| *addr).collect();
+    if let Some(linear) = execute(program, &vars)
+        .ok()
+        .and_then(|outcome| outcome.get(0).linear())
+    {
+        // A candidate may still fault on a real machine, e.g. when a variable is used as an address
+        match linear.solve(domains, target) {
+            Some(values) if halts_with(program, &vars, &values, target) => return Some(values),
+            Some(_) => {}
+            None => return None,
+        }
+    }
+
+    let mut values: Vec<_> = domains.iter().map(|(_, domain)| *domain.start()).collect();
+    if domains.iter().any(|(_, domain)| domain.is_empty()) {
+        return None;
+    }
+    loop {
+        if halts_with(program, &vars, &values, target) {
+            return Some(values);
+        }
+
+        // Advance to the next combination, the last variable changing fastest
+        let mut i = values.len();
+        loop {
+            if i == 0 {
+                return None;
+            }
+            i -= 1;
+            if values[i] < *domains[i].1.end() {
+                values[i] += 1;
+                break;
+            }
+            values[i] = *domains[i].1.start();
+        }
+    }
+}
+
+/// Whether `program` halts with `target` at address 0 within [`MAX_STEPS`] steps when the cells at
+/// `vars` hold `values`.
+fn halts_with(program: &[isize], vars: &[usize], values: &[isize], target: isize) -> bool {
+    let mut machine = Machine::new(program.to_vec(), (), ());
+    machine.set_step_limit(Some(MAX_STEPS));
+    vars.iter()
+        .zip(values)
+        .all(|(&addr, &value)| machine.poke(addr, value).is_ok())
+        && machine.run().ok() == Some(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_linear_expression() {
+        // [0] = 3 * ([13] + [14]) + 7
+        let program = [1, 13, 14, 0, 1002, 0, 3, 0, 1001, 0, 7, 0, 99, 0, 0];
+        let outcome = execute(&program, &[13, 14]).unwrap();
+
+        assert_eq!("((([13] + [14]) * 3) + 7)", outcome.get(0).to_string());
+        let linear = outcome.get(0).linear().unwrap();
+        assert_eq!(7, linear.constant);
+        assert_eq!(
+            vec![(13, 3), (14, 3)],
+            linear.terms.into_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn solves_linear_program() {
+        let program = [1, 13, 14, 0, 1002, 0, 3, 0, 1001, 0, 7, 0, 99, 0, 0];
+        let domains = [(13, 0..=9), (14, 0..=9)];
+
+        assert_eq!(Some(vec![0, 9]), solve(&program, &domains, 34));
+        assert_eq!(None, solve(&program, &domains, 35));
+    }
+
+    #[test]
+    fn rejects_faulting_solutions() {
+        // [3] = [[1]] + [9], then [0] = [1]
+        let program = [1, 0, 9, 3, 1001, 1, 0, 0, 99, 0];
+        let domains = [(1, -5..=5)];
+
+        assert_eq!(None, solve(&program, &domains, -3));
+        assert_eq!(Some(vec![2]), solve(&program, &domains, 2));
+    }
+
+    #[test]
+    fn falls_back_to_search() {
+        // [0] = [5] * [6]
+        let program = [2, 5, 6, 0, 99];
+        let error = execute(&[1005, 3, 4, 99, 99], &[3]).unwrap_err();
+
+        assert!(matches!(error, Error::SymbolicBranch { pc: 0 }));
+        assert!(execute(&program, &[5, 6])
+            .unwrap()
+            .get(0)
+            .linear()
+            .is_none());
+        assert_eq!(
+            Some(vec![3, 7]),
+            solve(&program, &[(5, 2..=9), (6, 2..=9)], 21)
+        );
+    }
+
+    #[test]
+    fn skips_endless_candidates() {
+        // Loops forever unless [12] is 2
+        let program = [1008, 12, 2, 13, 1005, 13, 11, 1105, 1, 7, 0, 99, 0, 0];
+
+        assert!(matches!(
+            execute(&program, &[]).unwrap_err(),
+            Error::Fault {
+                source: super::super::Error::BudgetExceeded { pc: 7, .. },
+                ..
+            }
+        ));
+        assert_eq!(Some(vec![2]), solve(&program, &[(12, 0..=2)], 1008));
+    }
+}
@@ -0,0 +1,217 @@
+//! Running machines on their own threads.
+//!
+//! [`spawn`] moves a machine to a new OS thread where it runs until it halts, so independent
+//! machines make use of multiple cores. Machines on different threads talk through channels, and
+//! unlike the non-blocking channel [I/O](super::io) of a cooperatively scheduled machine,
+//! [`BlockingInput`] and [`BlockingOutput`] wait until a value can be read or written. A timeout
+//! turns a machine which would wait forever into one which fails.
+
+use super::{Error, Fault, Input, Machine, Output, Result, Word};
+use std::panic;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::task::Poll;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// An input which blocks until a value is received.
+#[derive(Debug)]
+pub struct BlockingInput<W = isize> {
+    receiver: Receiver<W>,
+    timeout: Option<Duration>,
+}
+
+impl<W> BlockingInput<W> {
+    /// Creates an input which waits for a value for as long as it takes.
+    pub fn new(receiver: Receiver<W>) -> Self {
+        BlockingInput {
+            receiver,
+            timeout: None,
+        }
+    }
+
+    /// Creates an input which fails with `Error::InputTimeout` if no value arrives in time.
+    pub fn with_timeout(receiver: Receiver<W>, timeout: Duration) -> Self {
+        BlockingInput {
+            receiver,
+            timeout: Some(timeout),
+        }
+    }
+
+    /// The channel the input reads from, which still holds every value not read yet.
+    pub fn receiver(&self) -> &Receiver<W> {
+        &self.receiver
+    }
+}
+
+impl<W> Input<W> for BlockingInput<W> {
+    fn read(&mut self) -> Result<Poll<W>> {
+        let value = match self.timeout {
+            Some(timeout) => self.receiver.recv_timeout(timeout).map_err(|e| match e {
+                RecvTimeoutError::Timeout => Error::InputTimeout,
+                RecvTimeoutError::Disconnected => Error::InputClosed,
+            })?,
+            None => self.receiver.recv().map_err(|_| Error::InputClosed)?,
+        };
+        Ok(Poll::Ready(value))
+    }
+}
+
+/// An output which blocks until a bounded channel accepts a value.
+#[derive(Debug)]
+pub struct BlockingOutput<W = isize> {
+    sender: SyncSender<W>,
+    timeout: Option<Duration>,
+}
+
+impl<W> BlockingOutput<W> {
+    /// Creates an output which waits for room for as long as it takes.
+    pub fn new(sender: SyncSender<W>) -> Self {
+        BlockingOutput {
+            sender,
+            timeout: None,
+        }
+    }
+
+    /// Creates an output which fails with `Error::OutputTimeout` if the channel stays full.
+    pub fn with_timeout(sender: SyncSender<W>, timeout: Duration) -> Self {
+        BlockingOutput {
+            sender,
+            timeout: Some(timeout),
+        }
+    }
+}
+
+impl<W> Output<W> for BlockingOutput<W> {
+    fn write(&mut self, value: W) -> Result<Poll<()>> {
+        let deadline = match self.timeout {
+            Some(timeout) => Instant::now() + timeout,
+            None => {
+                self.sender.send(value).map_err(|_| Error::OutputClosed)?;
+                return Ok(Poll::Ready(()));
+            }
+        };
+
+        // Bounded channels cannot wait for room with a timeout, hence they are polled
+        let mut value = value;
+        loop {
+            match self.sender.try_send(value) {
+                Ok(()) => break Ok(Poll::Ready(())),
+                Err(TrySendError::Disconnected(_)) => break Err(Error::OutputClosed),
+                Err(TrySendError::Full(_)) if Instant::now() >= deadline => {
+                    break Err(Error::OutputTimeout)
+                }
+                Err(TrySendError::Full(returned)) => {
+                    value = returned;
+                    thread::sleep(Duration::from_millis(1));
+                }
+            }
+        }
+    }
+}
+
+/// A machine which finished running on its own thread, see [`Handle::join`].
+#[derive(Debug)]
+pub struct Exit<I, O, W = isize> {
+    /// The machine in its final state, together with its input and output.
+    pub machine: Machine<I, O, W>,
+    /// What [`Machine::run`] returned.
+    pub result: Result<W, Fault>,
+}
+
+/// A machine running on its own thread, see [`spawn`].
+#[derive(Debug)]
+pub struct Handle<I, O, W = isize> {
+    thread: JoinHandle<Exit<I, O, W>>,
+}
+
+impl<I, O, W> Handle<I, O, W> {
+    /// Waits for the machine to halt or fail.
+    ///
+    /// A panic on the thread of the machine is propagated.
+    pub fn join(self) -> Exit<I, O, W> {
+        self.thread
+            .join()
+            .unwrap_or_else(|payload| panic::resume_unwind(payload))
+    }
+}
+
+/// Runs a machine on a new thread until it halts or fails.
+///
+/// The machine is run with [`Machine::run`], hence non-blocking input and output which would
+/// have to wait fail instead.
+pub fn spawn<I, O, W>(mut machine: Machine<I, O, W>) -> Handle<I, O, W>
+where
+    I: Input<W> + Send + 'static,
+    O: Output<W> + Send + 'static,
+    W: Word + Send + Sync + 'static,
+{
+    let thread = thread::spawn(move || {
+        let result = machine.run();
+        Exit { machine, result }
+    });
+    Handle { thread }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::{channel, sync_channel};
+
+    #[test]
+    fn joins_with_final_state() {
+        // Doubles the input
+        let (tx, rx) = channel();
+        let (out_tx, out_rx) = sync_channel(1);
+        let machine = Machine::new(
+            vec![3, 9, 1002, 9, 2, 9, 4, 9, 99, 0],
+            BlockingInput::new(rx),
+            BlockingOutput::new(out_tx),
+        );
+
+        let handle = spawn(machine);
+        tx.send(21).unwrap();
+        assert_eq!(42, out_rx.recv().unwrap());
+
+        let exit = handle.join();
+        assert_eq!(3, exit.result.unwrap());
+        assert_eq!(42, exit.machine.peek(9));
+    }
+
+    #[test]
+    fn times_out_waiting_for_input() {
+        let (_tx, rx) = channel();
+        let machine = Machine::new(
+            vec![3, 0, 99],
+            BlockingInput::with_timeout(rx, Duration::from_millis(10)),
+            (),
+        );
+
+        let exit = spawn(machine).join();
+        assert!(matches!(
+            exit.result,
+            Err(Fault {
+                error: Error::InputTimeout,
+                ..
+            })
+        ));
+        assert_eq!(0, exit.machine.pc());
+    }
+
+    #[test]
+    fn times_out_waiting_for_output() {
+        let (tx, _rx) = sync_channel(0);
+        let machine = Machine::new(
+            vec![104, 1, 99],
+            (),
+            BlockingOutput::with_timeout(tx, Duration::from_millis(10)),
+        );
+
+        assert!(matches!(
+            spawn(machine).join().result,
+            Err(Fault {
+                error: Error::OutputTimeout,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,301 @@
+//! Execution tracing and profiling, see [`Machine::start_trace`](super::Machine::start_trace).
+
+use super::Mode;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+
+/// A resolved argument of a traced instruction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Operand<W = isize> {
+    /// The address the argument refers to, which is `None` for immediate arguments.
+    pub addr: Option<usize>,
+    /// The value of the argument before the instruction executed.
+    pub value: W,
+}
+
+/// An executed instruction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record<W = isize> {
+    pub step: usize,
+    pub pc: usize,
+    pub relative_base: isize,
+    pub opcode: u8,
+    pub mnemonic: &'static str,
+    /// The modes of the arguments, including ones the opcode does not take.
+    pub modes: [Mode; 3],
+    pub operands: Vec<Operand<W>>,
+    /// The addresses written by the instruction together with the values written.
+    pub writes: Vec<(usize, W)>,
+}
+
+impl<W: fmt::Display> Record<W> {
+    /// Writes the record as a single line of JSON.
+    pub fn write_json<T: Write>(&self, mut w: T) -> io::Result<()> {
+        write!(
+            w,
+            r#"{{"step":{},"pc":{},"relative_base":{},"opcode":{},"mnemonic":"#,
+            self.step, self.pc, self.relative_base, self.opcode
+        )?;
+        write_json_string(&mut w, self.mnemonic)?;
+        write!(w, r#","operands":["#)?;
+        for (i, (operand, mode)) in self.operands.iter().zip(&self.modes).enumerate() {
+            if i > 0 {
+                write!(w, ",")?;
+            }
+            write!(w, r#"{{"mode":{},"#, u8::from(*mode))?;
+            if let Some(addr) = operand.addr {
+                write!(w, r#""addr":{},"#, addr)?;
+            }
+            write!(w, r#""value":{}}}"#, operand.value)?;
+        }
+        write!(w, r#"],"writes":["#)?;
+        for (i, (addr, value)) in self.writes.iter().enumerate() {
+            if i > 0 {
+                write!(w, ",")?;
+            }
+            write!(w, r#"{{"addr":{},"value":{}}}"#, addr, value)?;
+        }
+        writeln!(w, "]}}")
+    }
+}
+
+/// Writes `s` as a quoted JSON string, since mnemonics of registered operations may contain any
+/// character.
+fn write_json_string<T: Write>(mut w: T, s: &str) -> io::Result<()> {
+    write!(w, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(w, "\\\"")?,
+            '\\' => write!(w, "\\\\")?,
+            '\n' => write!(w, "\\n")?,
+            '\r' => write!(w, "\\r")?,
+            '\t' => write!(w, "\\t")?,
+            c if c.is_control() => write!(w, "\\u{:04x}", c as u32)?,
+            c => write!(w, "{}", c)?,
+        }
+    }
+    write!(w, "\"")
+}
+
+/// A summary of where a program spends its time.
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    steps: usize,
+    opcodes: HashMap<(u8, &'static str), usize>,
+    pcs: HashMap<usize, usize>,
+}
+
+impl Profile {
+    fn add<W>(&mut self, record: &Record<W>) {
+        self.steps += 1;
+        *self
+            .opcodes
+            .entry((record.opcode, record.mnemonic))
+            .or_default() += 1;
+        *self.pcs.entry(record.pc).or_default() += 1;
+    }
+
+    /// The total number of executed instructions.
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// How often each opcode was executed by mnemonic, most executed first.
+    pub fn opcodes(&self) -> Vec<(&'static str, usize)> {
+        let mut opcodes: Vec<_> = self.opcodes.iter().collect();
+        opcodes.sort_by_key(|&(&(op, _), &n)| (std::cmp::Reverse(n), op));
+        opcodes
+            .into_iter()
+            .map(|(&(_, mnemonic), &n)| (mnemonic, n))
+            .collect()
+    }
+
+    /// The `n` most executed addresses together with how often they were executed.
+    pub fn hottest(&self, n: usize) -> Vec<(usize, usize)> {
+        let mut pcs: Vec<_> = self.pcs.iter().map(|(&pc, &n)| (pc, n)).collect();
+        pcs.sort_by_key(|&(pc, n)| (std::cmp::Reverse(n), pc));
+        pcs.truncate(n);
+        pcs
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "steps: {}", self.steps)?;
+        writeln!(f, "opcodes:")?;
+        for (mnemonic, n) in self.opcodes() {
+            writeln!(f, "  {:<4} {:>10}", mnemonic, n)?;
+        }
+        writeln!(f, "hottest addresses:")?;
+        for (pc, n) in self.hottest(10) {
+            writeln!(f, "  {:>6} {:>10}", pc, n)?;
+        }
+        Ok(())
+    }
+}
+
+/// The instructions executed by a machine.
+#[derive(Debug, Clone)]
+pub struct Trace<W = isize> {
+    records: Vec<Record<W>>,
+    keep_records: bool,
+    profile: Profile,
+}
+
+impl<W> Default for Trace<W> {
+    fn default() -> Self {
+        Trace::new()
+    }
+}
+
+impl<W> Trace<W> {
+    /// Creates a trace which keeps a record of every executed instruction.
+    pub fn new() -> Self {
+        Trace {
+            records: Vec::new(),
+            keep_records: true,
+            profile: Profile::default(),
+        }
+    }
+
+    /// Creates a trace which only keeps the profile, which is cheap enough for long runs.
+    pub fn profile_only() -> Self {
+        Trace {
+            keep_records: false,
+            ..Trace::new()
+        }
+    }
+
+    pub(super) fn push(&mut self, record: Record<W>) {
+        self.profile.add(&record);
+        if self.keep_records {
+            self.records.push(record);
+        }
+    }
+
+    pub fn records(&self) -> &[Record<W>] {
+        &self.records
+    }
+
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    /// Writes the records as JSON Lines, one record per line.
+    pub fn write_json_lines<T: Write>(&self, mut w: T) -> io::Result<()>
+    where
+        W: fmt::Display,
+    {
+        self.records
+            .iter()
+            .try_for_each(|record| record.write_json(&mut w))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{Machine, Program};
+    use std::collections::VecDeque;
+
+    #[test]
+    fn records_operands_and_writes() {
+        let mut machine = Machine::new(vec![1002, 4, 3, 4, 33], VecDeque::new(), ());
+        machine.start_trace(Trace::new());
+        machine.run().unwrap();
+
+        let trace = machine.stop_trace().unwrap();
+        let record = &trace.records()[0];
+        assert_eq!(
+            &[
+                Operand {
+                    addr: Some(4),
+                    value: 33
+                },
+                Operand {
+                    addr: None,
+                    value: 3
+                },
+                Operand {
+                    addr: Some(4),
+                    value: 33
+                },
+            ],
+            &record.operands[..]
+        );
+        assert_eq!(&[(4, 99)], &record.writes[..]);
+        let halt = &trace.records()[1];
+        assert_eq!((99, "HALT"), (halt.opcode, halt.mnemonic));
+    }
+
+    #[test]
+    fn json_lines() {
+        let mut machine = Machine::new(vec![1002, 4, 3, 4, 33], VecDeque::new(), ());
+        machine.start_trace(Trace::new());
+        machine.run().unwrap();
+
+        let mut json = Vec::new();
+        machine
+            .trace()
+            .unwrap()
+            .write_json_lines(&mut json)
+            .unwrap();
+        assert_eq!(
+            concat!(
+                r#"{"step":0,"pc":0,"relative_base":0,"opcode":2,"mnemonic":"MUL","operands":["#,
+                r#"{"mode":0,"addr":4,"value":33},{"mode":1,"value":3},"#,
+                r#"{"mode":0,"addr":4,"value":33}],"writes":[{"addr":4,"value":99}]}"#,
+                "\n",
+                r#"{"step":1,"pc":4,"relative_base":0,"opcode":99,"mnemonic":"HALT","#,
+                r#""operands":[],"writes":[]}"#,
+                "\n",
+            ),
+            String::from_utf8(json).unwrap()
+        );
+    }
+
+    #[test]
+    fn json_escapes_mnemonics() {
+        let record: Record = Record {
+            step: 0,
+            pc: 0,
+            relative_base: 0,
+            opcode: 42,
+            mnemonic: "S\"Q\\R\n",
+            modes: [Mode::Position; 3],
+            operands: Vec::new(),
+            writes: Vec::new(),
+        };
+
+        let mut json = Vec::new();
+        record.write_json(&mut json).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(42, value["opcode"]);
+        assert_eq!("S\"Q\\R\n", value["mnemonic"]);
+    }
+
+    #[test]
+    fn profile_counts_steps() {
+        let input = include_str!("../../input/2019/day5.txt");
+        let program: Program = input.parse().unwrap();
+
+        let mut machine = program.machine(VecDeque::from(vec![5]), Vec::new());
+        machine.start_trace(Trace::profile_only());
+        machine.run().unwrap();
+
+        let trace = machine.trace().unwrap();
+        assert!(trace.records().is_empty());
+        assert_eq!(machine.steps(), trace.profile().steps());
+        assert_eq!(
+            trace.profile().steps(),
+            trace
+                .profile()
+                .opcodes()
+                .iter()
+                .map(|&(_, n)| n)
+                .sum::<usize>()
+        );
+        assert_eq!(1, trace.profile().hottest(1).len());
+    }
+}
@@ -0,0 +1,172 @@
+//! Word types intcode machines can compute with.
+//!
+//! A [`Machine`](super::Machine) computes with `isize` unless told otherwise. Wider words such as
+//! `i128` make room for programs which produce huge values, and with the `bigint` feature enabled
+//! `num_bigint::BigInt` never overflows at all. What happens when a value does not fit is decided
+//! by an [`OverflowPolicy`].
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A value in the memory of a machine.
+///
+/// Addresses, relative base offsets and instructions are always `isize` sized, a word which does
+/// not fit into one is reported as an error when it is used as such.
+pub trait Word: Clone + PartialOrd + fmt::Debug + fmt::Display {
+    fn from_isize(value: isize) -> Self;
+    fn to_isize(&self) -> Option<isize>;
+
+    fn checked_add(&self, rhs: &Self) -> Option<Self>;
+    fn wrapping_add(&self, rhs: &Self) -> Self;
+    fn saturating_add(&self, rhs: &Self) -> Self;
+    fn checked_mul(&self, rhs: &Self) -> Option<Self>;
+    fn wrapping_mul(&self, rhs: &Self) -> Self;
+    fn saturating_mul(&self, rhs: &Self) -> Self;
+
+    fn zero() -> Self {
+        Self::from_isize(0)
+    }
+
+    fn is_zero(&self) -> bool {
+        *self == Self::zero()
+    }
+}
+
+macro_rules! impl_word {
+    ($($ty:ty),*) => {
+        $(
+            impl Word for $ty {
+                fn from_isize(value: isize) -> Self {
+                    value as $ty
+                }
+
+                fn to_isize(&self) -> Option<isize> {
+                    std::convert::TryFrom::try_from(*self).ok()
+                }
+
+                fn checked_add(&self, rhs: &Self) -> Option<Self> {
+                    <$ty>::checked_add(*self, *rhs)
+                }
+
+                fn wrapping_add(&self, rhs: &Self) -> Self {
+                    <$ty>::wrapping_add(*self, *rhs)
+                }
+
+                fn saturating_add(&self, rhs: &Self) -> Self {
+                    <$ty>::saturating_add(*self, *rhs)
+                }
+
+                fn checked_mul(&self, rhs: &Self) -> Option<Self> {
+                    <$ty>::checked_mul(*self, *rhs)
+                }
+
+                fn wrapping_mul(&self, rhs: &Self) -> Self {
+                    <$ty>::wrapping_mul(*self, *rhs)
+                }
+
+                fn saturating_mul(&self, rhs: &Self) -> Self {
+                    <$ty>::saturating_mul(*self, *rhs)
+                }
+            }
+        )*
+    };
+}
+
+impl_word!(isize, i64, i128);
+
+/// Big integers never overflow, hence every policy computes the exact result.
+#[cfg(feature = "bigint")]
+impl Word for num_bigint::BigInt {
+    fn from_isize(value: isize) -> Self {
+        value.into()
+    }
+
+    fn to_isize(&self) -> Option<isize> {
+        num_traits::ToPrimitive::to_isize(self)
+    }
+
+    fn checked_add(&self, rhs: &Self) -> Option<Self> {
+        Some(self + rhs)
+    }
+
+    fn wrapping_add(&self, rhs: &Self) -> Self {
+        self + rhs
+    }
+
+    fn saturating_add(&self, rhs: &Self) -> Self {
+        self + rhs
+    }
+
+    fn checked_mul(&self, rhs: &Self) -> Option<Self> {
+        Some(self * rhs)
+    }
+
+    fn wrapping_mul(&self, rhs: &Self) -> Self {
+        self * rhs
+    }
+
+    fn saturating_mul(&self, rhs: &Self) -> Self {
+        self * rhs
+    }
+}
+
+/// What a machine does when the result of an addition or multiplication does not fit in a word.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum OverflowPolicy {
+    /// Fault with `Error::Overflow`.
+    #[default]
+    Error,
+    /// Wrap around at the boundary of the word.
+    Wrap,
+    /// Clamp to the smallest or largest word.
+    Saturate,
+}
+
+impl OverflowPolicy {
+    /// Adds two words, returning `None` if the result overflows and the policy is to fail.
+    pub fn add<W: Word>(self, lhs: &W, rhs: &W) -> Option<W> {
+        match self {
+            OverflowPolicy::Error => lhs.checked_add(rhs),
+            OverflowPolicy::Wrap => Some(lhs.wrapping_add(rhs)),
+            OverflowPolicy::Saturate => Some(lhs.saturating_add(rhs)),
+        }
+    }
+
+    /// Multiplies two words, returning `None` if the result overflows and the policy is to fail.
+    pub fn mul<W: Word>(self, lhs: &W, rhs: &W) -> Option<W> {
+        match self {
+            OverflowPolicy::Error => lhs.checked_mul(rhs),
+            OverflowPolicy::Wrap => Some(lhs.wrapping_mul(rhs)),
+            OverflowPolicy::Saturate => Some(lhs.saturating_mul(rhs)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policies() {
+        assert_eq!(None, OverflowPolicy::Error.add(&i64::MAX, &1));
+        assert_eq!(Some(i64::MIN), OverflowPolicy::Wrap.add(&i64::MAX, &1));
+        assert_eq!(Some(i64::MAX), OverflowPolicy::Saturate.mul(&i64::MAX, &2));
+        assert_eq!(Some(6), OverflowPolicy::Error.mul(&2i128, &3));
+    }
+
+    #[test]
+    fn converts_to_isize() {
+        assert_eq!(Some(-3), (-3i128).to_isize());
+        assert_eq!(None, i128::MAX.to_isize());
+    }
+
+    #[cfg(feature = "bigint")]
+    #[test]
+    fn bigint_never_overflows() {
+        let max = num_bigint::BigInt::from_isize(isize::MAX);
+        let product = OverflowPolicy::Error.mul(&max, &max).unwrap();
+
+        assert!(product > max);
+        assert_eq!(None, product.to_isize());
+    }
+}